@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+
+use crate::utils::docker::docker_kill;
+
+#[cfg(test)]
+mod tests {
+    use crate::cancel::Cancellation;
+
+    #[test]
+    fn shared() {
+        let cancel = Cancellation::default();
+        let handle = cancel.clone();
+        assert!(!cancel.is_cancelled());
+        handle.cancel();
+        assert!(cancel.is_cancelled());
+        assert_eq!(cancel.timed_out(), None);
+
+        let timed_out = Cancellation::default();
+        timed_out.time_out(60);
+        assert!(timed_out.is_cancelled());
+        assert_eq!(timed_out.timed_out(), Some(60));
+        assert_eq!(
+            timed_out.reason(),
+            "stopped, the pipeline timed out after 60s"
+        );
+    }
+}
+
+#[derive(Clone, Default)]
+/// Aborts a running pipeline, from another thread. Its clones share the same state: keep one,
+/// and give another one to the pipeline's [launch options](crate::LaunchOptions::cancel)
+pub struct Cancellation(Arc<State>);
+
+#[derive(Default)]
+struct State {
+    cancelled: AtomicBool,
+    /// After how many seconds the pipeline [timed out](Cancellation::time_out), if it did
+    timed_out: Mutex<Option<u64>>,
+    /// The containers of the jobs running: several, when a stage's jobs run together
+    containers: Mutex<Vec<String>>,
+}
+
+impl Cancellation {
+    /// Aborts the pipeline: the containers of the running jobs, if any, are killed, so that
+    /// their steps fail at once. The commands of the jobs running on the host are killed too,
+    /// within a few milliseconds, as they poll it. No further step runs, and the remaining jobs
+    /// are skipped
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        for container in self.0.containers.lock().unwrap().iter() {
+            if let Err(e) = docker_kill(container) {
+                warn!("Could not kill container {}: {}", container, e);
+            }
+        }
+    }
+
+    /// Aborts the pipeline like [cancel](Cancellation::cancel) does, because it ran for longer
+    /// than its [timeout](crate::conf::FakeCIRepoConfig::pipeline_timeout), of `after` seconds
+    pub(crate) fn time_out(&self, after: u64) {
+        *self.0.timed_out.lock().unwrap() = Some(after);
+        self.cancel();
+    }
+
+    /// After how many seconds the pipeline [timed out](Cancellation::time_out), if it did
+    pub fn timed_out(&self) -> Option<u64> {
+        *self.0.timed_out.lock().unwrap()
+    }
+
+    /// Why the pipeline was stopped, as told in the logs of its jobs. Ex: `cancelled`
+    pub(crate) fn reason(&self) -> String {
+        match self.timed_out() {
+            Some(after) => format!("stopped, the pipeline timed out after {}s", after),
+            None => "cancelled".to_string(),
+        }
+    }
+
+    /// Was the pipeline [cancelled](Cancellation::cancel), or [timed out](Cancellation::time_out)?
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Adds a container to kill when cancelled: a running job's
+    pub(crate) fn running_in(&self, container: &str) {
+        self.0
+            .containers
+            .lock()
+            .unwrap()
+            .push(container.to_string());
+    }
+
+    /// Forgets a container [running a job](Cancellation::running_in), once it's done
+    pub(crate) fn done_in(&self, container: &str) {
+        self.0.containers.lock().unwrap().retain(|c| c != container);
+    }
+}
@@ -1,26 +1,717 @@
 /// Defines what makes for a valid configuration
-use crate::Env;
-use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
 
+use crate::{Env, ExecutionContext};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::{schema_for, JsonSchema};
+use semver::Version;
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_yaml::Value;
+
+use crate::error::FakeCiError;
 use crate::utils::docker::{rng_docker_chars, DOCKER_NAME_CHARSET};
 
 #[cfg(test)]
 mod tests {
-    use crate::conf::Image;
+    use crate::conf::{
+        format_config, init_config, interpolate_secrets, json_schema, FakeCIDockerImage,
+        FakeCIRepoConfig, Image, ImageVersion, RetryOn, Runner, StderrPattern,
+    };
+    use crate::error::FakeCiError;
+    use crate::utils::get_job_image_or_default;
+    use crate::utils::git::Commit;
     use crate::utils::tests::{deser_yaml, get_sample_resource_file};
-    use crate::Env;
+    use crate::{Env, ExecutionContext};
+    use chrono::{TimeZone, Utc};
+    use tempdir::TempDir;
 
     #[test]
     fn basic_config() {
         let s = get_sample_resource_file("basic_config.yml").expect("could not find basic_config");
         let c = deser_yaml(&s).expect("could not deserialize basic config");
         assert_eq!(c.pipeline.len(), 2);
-        let j0 = c.pipeline.get(0).unwrap();
+        let j0 = c.pipeline.first().unwrap();
         assert_eq!(j0.name, "job 0");
         assert_eq!(j0.volumes.len(), 0);
         assert_eq!(j0.env, Env::new());
         assert_eq!(j0.image, Some(Image::Existing("ubuntu".to_string())));
         assert_eq!(j0.steps.len(), 2);
+        assert!(j0.mount_source);
+    }
+
+    #[test]
+    fn anchors() {
+        let s = get_sample_resource_file("anchors.yml").expect("could not find anchors.yml");
+        let c = deser_yaml(&s).expect("could not deserialize anchors.yml");
+        assert_eq!(c.pipeline.len(), 2);
+        let (j0, j1) = (&c.pipeline[0], &c.pipeline[1]);
+        assert_eq!(j0.steps, j1.steps);
+        assert_eq!(j0.image, j1.image);
+        assert_ne!(j0.env, j1.env);
+        let expected = Image::ExistingFull(FakeCIDockerImage {
+            name: "busybox".to_string(),
+            privileged: false,
+        });
+        assert_eq!(get_job_image_or_default(j0, &c).unwrap(), &expected);
+        assert_eq!(get_job_image_or_default(j1, &c).unwrap(), &expected);
+    }
+
+    #[test]
+    fn named_build_image() {
+        let c = deser_yaml(
+            "pipeline:
+  - name: app
+    image:
+      name: registry.example.org/app
+      dockerfile: Dockerfile
+    steps: [make]
+  - name: tools
+    image:
+      name: busybox
+      privileged: true
+    steps: [make]",
+        )
+        .expect("could not deserialize config");
+        match &c.pipeline[0].image {
+            Some(Image::Build(b)) => {
+                assert_eq!(b.name.as_deref(), Some("registry.example.org/app"));
+                assert_eq!(b.dockerfile.as_deref(), Some("Dockerfile"));
+            }
+            i => panic!("not a built image: {:?}", i),
+        }
+        assert_eq!(
+            c.pipeline[1].image,
+            Some(Image::ExistingFull(FakeCIDockerImage {
+                name: "busybox".to_string(),
+                privileged: true,
+            }))
+        );
+    }
+
+    #[test]
+    fn image_versions() {
+        let digest =
+            "ubuntu@sha256:45b23dee08af5e43a7fea6c4cf9c25ccf269ee113168c19722f87876677c5cb2";
+        assert_eq!(ImageVersion::of(digest), ImageVersion::Digest);
+        assert_eq!(
+            ImageVersion::of("ubuntu:22.04"),
+            ImageVersion::Tag("22.04".to_string())
+        );
+        assert_eq!(ImageVersion::of("ubuntu"), ImageVersion::None);
+        assert_eq!(
+            ImageVersion::of("localhost:5000/team/app"),
+            ImageVersion::None
+        );
+        assert_eq!(
+            ImageVersion::of("localhost:5000/app:1.2"),
+            ImageVersion::Tag("1.2".to_string())
+        );
+        assert!(ImageVersion::of("ubuntu").is_floating());
+        assert!(ImageVersion::of("ubuntu:latest").is_floating());
+        assert!(!ImageVersion::of("ubuntu:22.04").is_floating());
+        assert!(!ImageVersion::of(digest).is_floating());
+    }
+
+    #[test]
+    fn schema() {
+        let schema: serde_json::Value = serde_json::from_str(&json_schema()).unwrap();
+        assert_eq!(schema["title"], "FakeCIRepoConfig");
+        assert!(schema["properties"]["pipeline"].is_object());
+        assert!(schema["required"]
+            .as_array()
+            .unwrap()
+            .contains(&"pipeline".into()));
+        // untagged: an image is any of its variants
+        assert_eq!(
+            schema["definitions"]["Image"]["anyOf"]
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+        // a step is a command, or a complete step
+        assert_eq!(
+            schema["definitions"]["StepSpec"]["anyOf"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn stages() {
+        let c = deser_yaml(
+            "stages: [build, test, deploy]
+pipeline:
+  - name: deploy
+    stage: deploy
+    steps: [make deploy]
+  - name: unit
+    stage: test
+    steps: [make test]
+  - name: build
+    stage: build
+    steps: [make]
+  - name: lint
+    stage: test
+    steps: [make lint]",
+        )
+        .unwrap();
+        let names = |c: &FakeCIRepoConfig| -> Vec<Vec<String>> {
+            c.job_stages()
+                .unwrap()
+                .iter()
+                .map(|s| s.iter().map(|j| j.name.to_string()).collect())
+                .collect()
+        };
+        assert_eq!(
+            names(&c),
+            vec![vec!["build"], vec!["unit", "lint"], vec!["deploy"]]
+        );
+        let c =
+            deser_yaml("pipeline:\n  - name: b\n    steps: [make]\n  - name: a\n    steps: [make]")
+                .unwrap();
+        assert_eq!(names(&c), vec![vec!["b"], vec!["a"]]);
+
+        for (invalid, error) in [
+            (
+                "stages: [build]\npipeline:\n  - name: a\n    steps: [make]",
+                "Job \"a\" has no stage; pick one of build",
+            ),
+            (
+                "stages: [build]\npipeline:\n  - name: a\n    stage: test\n    steps: [make]",
+                "Job \"a\" is in stage test, which isn't in the stages: build",
+            ),
+            (
+                "pipeline:\n  - name: a\n    stage: test\n    steps: [make]",
+                "Job \"a\" has a stage, but the pipeline has no stages",
+            ),
+            (
+                "stages: [build]
+pipeline:
+  - name: a
+    stage: build
+    steps: [make]
+  - name: b
+    stage: build
+    needs_artifacts_from: [a]
+    steps: [make]",
+                "Job \"b\" needs the artifacts of job \"a\", which isn't in an earlier stage",
+            ),
+        ] {
+            assert!(matches!(
+                deser_yaml(invalid).unwrap().job_stages(),
+                Err(FakeCiError::Config(e)) if e == error
+            ));
+        }
+    }
+
+    #[test]
+    fn only_jobs() {
+        let c = deser_yaml(
+            "pipeline:
+  - name: build
+    steps: [make]
+  - name: lint
+    steps: [make lint]
+  - name: package
+    needs_artifacts_from: [build]
+    steps: [make dist]
+  - name: deploy
+    needs_artifacts_from: [package]
+    steps: [make deploy]",
+        )
+        .unwrap();
+        let names = |c: &FakeCIRepoConfig| -> Vec<String> {
+            c.pipeline.iter().map(|j| j.name.to_string()).collect()
+        };
+        let all = names(&c);
+        let only = |globs: &[&str]| {
+            let globs: Vec<String> = globs.iter().map(|g| g.to_string()).collect();
+            deser_yaml(&serde_yaml::to_string(&c).unwrap())
+                .unwrap()
+                .only_jobs(&globs)
+        };
+        assert_eq!(names(&only(&[]).unwrap()), all);
+        assert_eq!(names(&only(&["lint"]).unwrap()), vec!["lint"]);
+        assert_eq!(
+            names(&only(&["l*", "build"]).unwrap()),
+            vec!["build", "lint"]
+        );
+        // the jobs whose artifacts are needed come along, in the pipeline's order
+        assert_eq!(
+            names(&only(&["deploy"]).unwrap()),
+            vec!["build", "package", "deploy"]
+        );
+        assert!(matches!(only(&["test"]), Err(FakeCiError::Config(_))));
+        assert!(matches!(only(&["[oops"]), Err(FakeCiError::Config(_))));
+    }
+
+    #[test]
+    fn secret_interpolation() {
+        let secrets = Env::from([("TOKEN".to_string(), "s3cr3t".to_string())]);
+        let mut masked = vec![];
+        assert_eq!(
+            interpolate_secrets(
+                "registry.example.org/${secret.TOKEN}/app:1.0",
+                &secrets,
+                &mut masked
+            )
+            .unwrap(),
+            "registry.example.org/s3cr3t/app:1.0"
+        );
+        assert_eq!(masked, vec!["s3cr3t"]);
+        // variables aren't secrets
+        assert_eq!(
+            interpolate_secrets("app:${variables.tag}", &secrets, &mut masked).unwrap(),
+            "app:${variables.tag}"
+        );
+        assert_eq!(masked.len(), 1);
+        match interpolate_secrets("/srv/${secret.NOPE}:/data", &secrets, &mut masked) {
+            Err(FakeCiError::Config(e)) => {
+                assert_eq!(e, "Undefined secret NOPE, in \"/srv/${secret.NOPE}:/data\"")
+            }
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn image_name_template() {
+        let conf = deser_yaml(
+            "default:
+  image:
+    name: registry.example.org/base:${timestamp}
+    dockerfile: base.Dockerfile
+pipeline:
+  - name: app
+    image:
+      name: registry.example.org/app:${branch}-${commit_short}
+      dockerfile: Dockerfile
+    steps: [make]
+  - name: random name
+    image:
+      dockerfile: Dockerfile
+    steps: [make]",
+        )
+        .expect("could not deserialize config");
+        let ctx = ExecutionContext {
+            branch: "feature/login".to_string(),
+            commit: Commit {
+                hash: "0123456789abcdef".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let start = Utc.with_ymd_and_hms(2021, 11, 28, 12, 0, 0).unwrap();
+        let rendered = conf
+            .render_image_names(&ctx, start)
+            .expect("could not render the image names");
+        let name = |image: &Option<Image>| match image {
+            Some(Image::Build(b)) => b.name.clone(),
+            i => panic!("not a built image: {:?}", i),
+        };
+        assert_eq!(
+            name(&rendered.default.as_ref().unwrap().image),
+            Some("registry.example.org/base:20211128-120000".to_string())
+        );
+        // a tag can't hold a /
+        assert_eq!(
+            name(&rendered.pipeline[0].image),
+            Some("registry.example.org/app:feature-login-0123456".to_string())
+        );
+        assert_eq!(name(&rendered.pipeline[1].image), None);
+
+        let conf = deser_yaml(
+            "pipeline:
+  - name: app
+    image:
+      name: app:${version}
+      context: app
+    steps: [make]",
+        )
+        .expect("could not deserialize config");
+        assert!(matches!(
+            conf.render_image_names(&ctx, start),
+            Err(FakeCiError::Config(e)) if e.contains("Unknown placeholder ${version}")
+        ));
+    }
+
+    #[test]
+    fn variables() {
+        let conf = deser_yaml(
+            "variables:
+  tag: \"1.2\"
+  registry: registry.example.org
+pipeline:
+  - name: release ${variables.tag}
+    image: ${variables.registry}/myapp:${variables.tag}
+    env:
+      TAG: ${variables.tag}
+    steps:
+      - make release VERSION=${variables.tag}
+      - echo '${HOME} is left to the shell'",
+        )
+        .expect("could not deserialize config");
+        let resolved = conf
+            .resolve_variables()
+            .expect("could not resolve variables");
+        let job = &resolved.pipeline[0];
+        assert_eq!(job.name, "release 1.2");
+        assert_eq!(
+            job.image,
+            Some(Image::Existing(
+                "registry.example.org/myapp:1.2".to_string()
+            ))
+        );
+        assert_eq!(job.env["TAG"], "1.2");
+        assert_eq!(job.steps[0].exec, vec!["make release VERSION=1.2"]);
+        assert_eq!(
+            job.steps[1].exec,
+            vec!["echo '${HOME} is left to the shell'"]
+        );
+        assert_eq!(resolved.variables, conf.variables);
+
+        let conf = deser_yaml(
+            "variables:
+  tag: \"1.2\"
+pipeline:
+  - name: release
+    image: myapp:${variables.version}
+    steps: [make]",
+        )
+        .expect("could not deserialize config");
+        assert!(matches!(
+            conf.resolve_variables(),
+            Err(FakeCiError::Config(e)) if e.contains("Undefined variable version")
+        ));
+    }
+
+    #[test]
+    fn format() {
+        let messy = "pipeline:
+  - steps:
+      - exec: [cargo build]
+      - name: test
+        exec: [cargo test]
+        allow_failure: false
+    name: build
+    mount_source: true
+    env: {B: \"2\", A: \"1\"}
+    image:
+      name: rust:1.60
+      privileged: false
+    runner: docker
+    volumes: []
+skip_tags: [\"[skip ci]\", \"[ci skip]\"]
+min_pass_ratio: 0.5
+default:
+  keep_on_failure: false
+  image: alpine:3.16
+";
+        let formatted = format_config(messy).expect("could not format");
+        assert_eq!(
+            formatted,
+            "---
+pipeline:
+  - name: build
+    image:
+      name: \"rust:1.60\"
+    steps:
+      - cargo build
+      - name: test
+        exec:
+          - cargo test
+    env:
+      A: \"1\"
+      B: \"2\"
+default:
+  image: \"alpine:3.16\"
+min_pass_ratio: 0.5
+"
+        );
+        assert_eq!(deser_yaml(&formatted).unwrap(), deser_yaml(messy).unwrap());
+        // idempotent
+        assert_eq!(format_config(&formatted).unwrap(), formatted);
+        for f in [
+            "basic_config.yml",
+            "anchors.yml",
+            "docker_build.yml",
+            "defaults.yml",
+        ] {
+            let s = get_sample_resource_file(f).expect("could not read sample");
+            let once = format_config(&s).expect("could not format");
+            assert_eq!(format_config(&once).unwrap(), once);
+        }
+        assert!(matches!(
+            format_config("pipeline: [ oops"),
+            Err(FakeCiError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn init() {
+        let dir = TempDir::new("fake-ci-init").expect("could not create temp dir");
+        let path = dir.path().join(".fakeci.yml");
+        init_config(&path, false).expect("could not write the starter config");
+        let s = std::fs::read_to_string(&path).expect("starter config not written");
+        assert!(s.contains("# the docker image the job runs in"));
+        let c = deser_yaml(&s).expect("invalid starter config");
+        assert_eq!(c.pipeline.len(), 1);
+        assert_eq!(
+            c.pipeline[0].image,
+            Some(Image::Existing("ubuntu".to_string()))
+        );
+        assert_eq!(c.pipeline[0].secrets, vec!["GREETER".to_string()]);
+        let round_trip = deser_yaml(&serde_yaml::to_string(&c).unwrap()).unwrap();
+        assert_eq!(round_trip, c);
+
+        std::fs::write(&path, "pipeline: []").unwrap();
+        assert!(matches!(
+            init_config(&path, false),
+            Err(FakeCiError::Config(e)) if e.contains("already exists")
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "pipeline: []");
+        init_config(&path, true).expect("could not overwrite");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), s);
+    }
+
+    #[test]
+    fn step_shortcut() {
+        let full = deser_yaml(
+            "pipeline:
+  - name: build
+    steps:
+      - exec:
+          - make build
+      - exec:
+          - make test",
+        )
+        .expect("could not deserialize full steps");
+        let short = deser_yaml(
+            "pipeline:
+  - name: build
+    steps:
+      - make build
+      - make test",
+        )
+        .expect("could not deserialize step shortcuts");
+        let inline =
+            deser_yaml("pipeline:\n  - name: build\n    steps: [\"make build\", \"make test\"]")
+                .expect("could not deserialize inline step shortcuts");
+        assert_eq!(full.pipeline[0].steps, short.pipeline[0].steps);
+        assert_eq!(full.pipeline[0].steps, inline.pipeline[0].steps);
+        assert_eq!(short.pipeline[0].steps[1].exec, vec!["make test"]);
+        assert_eq!(short.pipeline[0].steps[1].name, None);
+
+        // both forms can be mixed
+        let mixed = deser_yaml(
+            "pipeline:
+  - name: build
+    steps:
+      - make build
+      - name: test
+        exec: [make test]
+        allow_failure: true",
+        )
+        .expect("could not deserialize mixed steps");
+        let steps = &mixed.pipeline[0].steps;
+        assert_eq!(steps[0], full.pipeline[0].steps[0]);
+        assert_eq!(steps[1].name, Some("test".to_string()));
+        assert!(steps[1].allow_failure);
+        assert!(deser_yaml("pipeline:\n  - name: build\n    steps: [42]").is_err());
+    }
+
+    #[test]
+    fn images() {
+        let c = deser_yaml(
+            "pipeline:
+  - name: test
+    images:
+      - debian:12
+      - name: alpine:3.19
+        privileged: true
+      - dockerfile: ci/fedora.Dockerfile
+    env:
+      CI: \"1\"
+    steps: [make test]
+  - name: package
+    image: debian:12
+    steps: [make dist]",
+        )
+        .expect("could not deserialize images")
+        .expand_images()
+        .expect("could not expand images");
+        let names: Vec<&str> = c.pipeline.iter().map(|j| j.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "test [debian:12]",
+                "test [alpine:3.19]",
+                "test [ci/fedora.Dockerfile]",
+                "package"
+            ]
+        );
+        assert_eq!(
+            c.pipeline[0].image,
+            Some(Image::Existing("debian:12".to_string()))
+        );
+        assert!(c.pipeline[1].image.as_ref().unwrap().is_privileged());
+        assert!(matches!(&c.pipeline[2].image, Some(Image::Build(_))));
+        assert!(c.pipeline[..3].iter().all(|j| j.images.is_empty()
+            && j.env["CI"] == "1"
+            && j.steps[0].exec == ["make test"]));
+
+        let both = deser_yaml(
+            "pipeline:\n  - name: test\n    image: debian\n    images: [alpine]\n    steps: [make]",
+        )
+        .unwrap();
+        assert!(matches!(
+            both.expand_images(),
+            Err(FakeCiError::Config(e)) if e.contains("both image & images")
+        ));
+    }
+
+    #[test]
+    fn default_timeout_and_retry() {
+        let c = deser_yaml(
+            "default:
+  timeout: 600
+  retry: 2
+pipeline:
+  - name: inherits
+    steps: [make]
+  - name: overrides
+    timeout: 60
+    steps:
+      - make
+      - exec: [make test]
+        retry: 0",
+        )
+        .expect("could not deserialize defaults")
+        .apply_defaults();
+        assert_eq!(c.pipeline[0].timeout, Some(600));
+        assert_eq!(c.pipeline[0].steps[0].retry, Some(2));
+        assert_eq!(c.pipeline[1].timeout, Some(60));
+        assert_eq!(c.pipeline[1].steps[0].retry, Some(2));
+        assert_eq!(c.pipeline[1].steps[1].retry, Some(0));
+
+        let c = deser_yaml("pipeline:\n  - name: build\n    steps: [make]")
+            .unwrap()
+            .apply_defaults();
+        assert_eq!(c.pipeline[0].timeout, None);
+        assert_eq!(c.pipeline[0].steps[0].retry, None);
+    }
+
+    #[test]
+    fn retry_on() {
+        let c = deser_yaml(
+            "pipeline:
+  - name: fetch
+    steps:
+      - exec: [curl -f https://example.org]
+        retry: 2
+        retry_on:
+          exit_codes: [7, 56]
+          stderr: [(?i)connection reset]",
+        )
+        .expect("could not deserialize retry_on");
+        let retry_on = &c.pipeline[0].steps[0].retry_on;
+        assert_eq!(retry_on.exit_codes, vec![7, 56]);
+        assert!(retry_on.matches(Some(7), ""));
+        assert!(retry_on.matches(Some(1), "curl: (35) Connection Reset by peer"));
+        assert!(!retry_on.matches(Some(1), "error[E0308]"));
+        // killed by a signal
+        assert!(!retry_on.matches(None, ""));
+        assert_eq!(retry_on.stderr[0].as_str(), "(?i)connection reset");
+        assert!(serde_yaml::to_string(retry_on)
+            .unwrap()
+            .contains("(?i)connection reset"));
+
+        assert!(RetryOn::default().matches(Some(1), ""));
+        assert!(matches!(
+            StderrPattern::new("("),
+            Err(FakeCiError::Config(e)) if e.contains("Invalid retry_on regex (")
+        ));
+        // an invalid regex fails the config as it is loaded
+        let err = deser_yaml(
+            "pipeline:
+  - name: fetch
+    steps:
+      - exec: [curl -f https://example.org]
+        retry_on:
+          stderr: [\"(\"]",
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid retry_on regex ("),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn min_version() {
+        let mut c = deser_yaml("pipeline: []\nmin_version: 0.5.0").unwrap();
+        assert!(c.check_min_version("0.5.0").is_ok());
+        assert!(c.check_min_version("1.2.3").is_ok());
+        assert!(matches!(
+            c.check_min_version("0.4.9"),
+            Err(FakeCiError::Config(e)) if e.contains("requires fake-ci 0.5.0")
+        ));
+        c.min_version = Some("0.5".to_string());
+        assert!(matches!(
+            c.check_min_version("0.5.0"),
+            Err(FakeCiError::Config(e)) if e.contains("Invalid min_version")
+        ));
+        c.min_version = None;
+        assert!(c.check_min_version("0.1.0").is_ok());
+    }
+
+    #[test]
+    fn min_pass_ratio() {
+        let mut c = deser_yaml("pipeline: []\nmin_pass_ratio: 0.8").unwrap();
+        assert_eq!(c.min_pass_ratio, Some(0.8));
+        assert!(c.check_min_pass_ratio().is_ok());
+        c.min_pass_ratio = Some(1.5);
+        assert!(matches!(
+            c.check_min_pass_ratio(),
+            Err(FakeCiError::Config(_))
+        ));
+        c.min_pass_ratio = None;
+        assert!(c.check_min_pass_ratio().is_ok());
+    }
+
+    #[test]
+    fn skip_tags() {
+        let c = deser_yaml("pipeline: []").unwrap();
+        assert_eq!(c.skip_tags, vec!["[skip ci]", "[ci skip]"]);
+        let c = deser_yaml("pipeline: []\nskip_tags: []").unwrap();
+        assert!(c.skip_tags.is_empty());
+    }
+
+    #[test]
+    fn host_runner() {
+        let s =
+            get_sample_resource_file("host_runner.yml").expect("could not find host_runner.yml");
+        let c = deser_yaml(&s).expect("could not deserialize host_runner.yml");
+        assert_eq!(c.pipeline[0].runner, Runner::Host);
+        assert!(c.pipeline[0].runs_on_host());
+        assert_eq!(c.pipeline[1].runner, Runner::Docker);
+        assert!(c.pipeline[1].runs_on_host());
+    }
+
+    #[test]
+    fn no_mount_source() {
+        let s = get_sample_resource_file("no_mount_source.yml").expect("could not find file");
+        let c = deser_yaml(&s).expect("could not deserialize config");
+        assert!(!c.pipeline.first().unwrap().mount_source);
     }
 
     #[test]
@@ -29,7 +720,7 @@ mod tests {
             &get_sample_resource_file("docker_build.yml").expect("could not find docker_build"),
         )
         .expect("could not parse docker_build");
-        let j0 = c.pipeline.get(0).unwrap();
+        let j0 = c.pipeline.first().unwrap();
         assert!(j0.image.is_some());
         let image = j0.image.as_ref().unwrap();
         match image {
@@ -47,52 +738,619 @@ mod tests {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Eq, PartialEq)]
 /// Some default that may or may not be present
 pub struct FakeCIDefaultConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// An optional docker Image definition
     pub image: Option<Image>,
-    #[serde(default)]
+    #[serde(
+        default,
+        skip_serializing_if = "Env::is_empty",
+        serialize_with = "sorted"
+    )]
     /// default environment. Will be extended by individual jobs' envs
     pub env: Env,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default platform of the images. Ex: `linux/amd64`
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Arguments passed as-is to `docker run`. Jobs' [extra_docker_args](FakeCIJob::extra_docker_args)
+    /// are appended to these
+    pub extra_docker_args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Entries added to the containers' `/etc/hosts`. Jobs'
+    /// [extra_hosts](FakeCIJob::extra_hosts) are appended to these
+    pub extra_hosts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// A `.env` file to load, relative to the repository root. Its values have the lowest
+    /// precedence: any explicitly configured env overrides them
+    pub env_file: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Keep the containers of failed jobs, to inspect them. Default: false
+    pub keep_on_failure: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default [shell](FakeCIJob::shell) of the jobs
+    pub shell: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default [timeout](FakeCIJob::timeout) of the jobs, in seconds
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default [retry](FakeCIStep::retry) of the jobs' steps
+    pub retry: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default [heartbeat interval](FakeCIJob::heartbeat_interval) of the jobs, in seconds
+    pub heartbeat_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default [maximum size of the artifacts](FakeCIJob::max_artifact_size) of the jobs, in bytes
+    pub max_artifact_size: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
 /// Represents an entire `.fakeci.yml`
 pub struct FakeCIRepoConfig {
     /// A list of jobs
     pub pipeline: Vec<FakeCIJob>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// Some defaults to be used if we don't want to repeat the same stuff over & over
     pub default: Option<FakeCIDefaultConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The oldest fake-ci version able to run this config, as a semver version. Ex: `0.5.0`
+    pub min_version: Option<String>,
+    #[serde(
+        default = "skip_tags_default",
+        skip_serializing_if = "is_default_skip_tags"
+    )]
+    /// If the commit message contains one of these, no job runs. Default: `[skip ci]` & `[ci skip]`
+    pub skip_tags: Vec<String>,
+    /// The pipeline succeeds if at least this ratio of its jobs succeed, between 0 & 1.
+    /// Ex: `0.8`. [allow_failure](FakeCIJob::allow_failure) jobs aren't counted.
+    /// Default: all the jobs must succeed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_pass_ratio: Option<f32>,
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "sorted"
+    )]
+    /// Values to reuse across the pipeline, as `${variables.NAME}`. Unlike the env, they are
+    /// replaced by fake-ci before running, anywhere in the config: `image: myapp:${variables.tag}`
+    pub variables: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// The stages of the pipeline, in order. Ex: `[build, test, deploy]`. The jobs of a
+    /// [stage](FakeCIJob::stage) run together, once all those of the previous one are done; a
+    /// failure, unless [allowed](FakeCIJob::allow_failure), skips the next stages.
+    /// Default: no stages, the jobs running one after the other
+    pub stages: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// How long the whole pipeline may run, in seconds. Past it, the container of the running
+    /// job is killed, failing it, and the remaining jobs are skipped. Default: no limit
+    pub pipeline_timeout: Option<u64>,
+}
+
+lazy_static! {
+    static ref VARIABLE_PATTERN: Regex =
+        Regex::new(r"\$\{variables\.([A-Za-z0-9_\-]+)\}").expect("could not compile pattern");
+}
+
+/// Replaces the [variables](FakeCIRepoConfig::variables) referenced in the strings of `value`,
+/// recursively. Mapping keys are left as is.
+fn interpolate(value: &mut Value, variables: &HashMap<String, String>) -> Result<(), FakeCiError> {
+    match value {
+        Value::String(s) if VARIABLE_PATTERN.is_match(s) => {
+            let mut undefined = None;
+            let replaced = VARIABLE_PATTERN.replace_all(s, |c: &Captures| {
+                variables.get(&c[1]).cloned().unwrap_or_else(|| {
+                    undefined.get_or_insert_with(|| c[1].to_string());
+                    String::new()
+                })
+            });
+            if let Some(name) = undefined {
+                return Err(FakeCiError::Config(format!(
+                    "Undefined variable {}, in \"{}\"",
+                    name, s
+                )));
+            }
+            *s = replaced.to_string();
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                interpolate(item, variables)?;
+            }
+        }
+        Value::Mapping(map) => {
+            for (_, v) in map.iter_mut() {
+                interpolate(v, variables)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+lazy_static! {
+    static ref SECRET_PATTERN: Regex =
+        Regex::new(r"\$\{secret\.([A-Za-z0-9_\-]+)\}").expect("could not compile pattern");
+}
+
+/// Replaces the `${secret.NAME}` of `s` by the values of the `secrets`, which are added to
+/// `masked`, to be hidden from the logs. Only done when running, so that the values never end up
+/// in the config. Errors if a secret isn't defined
+pub(crate) fn interpolate_secrets(
+    s: &str,
+    secrets: &Env,
+    masked: &mut Vec<String>,
+) -> Result<String, FakeCiError> {
+    let mut undefined = None;
+    let replaced = SECRET_PATTERN.replace_all(s, |c: &Captures| match secrets.get(&c[1]) {
+        Some(v) => {
+            masked.push(v.to_string());
+            v.to_string()
+        }
+        None => {
+            undefined.get_or_insert_with(|| c[1].to_string());
+            String::new()
+        }
+    });
+    if let Some(name) = undefined {
+        return Err(FakeCiError::Config(format!(
+            "Undefined secret {}, in \"{}\"",
+            name, s
+        )));
+    }
+    Ok(replaced.to_string())
+}
+
+lazy_static! {
+    static ref IMAGE_NAME_PLACEHOLDER: Regex =
+        Regex::new(r"\$\{([a-z_]+)\}").expect("could not compile pattern");
+}
+
+impl FakeCIDockerBuild {
+    /// Replaces the placeholders of the [name](FakeCIDockerBuild::name) by the values of the
+    /// pipeline run in `ctx`, started at `start`. `${branch}` gets the characters a tag can't
+    /// hold replaced by `-`, and `${timestamp}` is the start, in UTC, as `20211128-120000`
+    fn render_name(
+        &mut self,
+        ctx: &ExecutionContext,
+        start: DateTime<Utc>,
+    ) -> Result<(), FakeCiError> {
+        let name = match &self.name {
+            Some(n) if IMAGE_NAME_PLACEHOLDER.is_match(n) => n,
+            _ => return Ok(()),
+        };
+        let mut unknown = None;
+        let rendered = IMAGE_NAME_PLACEHOLDER.replace_all(name, |c: &Captures| match &c[1] {
+            "branch" => ctx
+                .branch
+                .chars()
+                .map(
+                    |c| match c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                        true => c,
+                        false => '-',
+                    },
+                )
+                .collect(),
+            "commit" => ctx.commit.hash.to_string(),
+            "commit_short" => ctx.commit.hash.chars().take(7).collect(),
+            "timestamp" => start.format("%Y%m%d-%H%M%S").to_string(),
+            other => {
+                unknown.get_or_insert_with(|| other.to_string());
+                String::new()
+            }
+        });
+        if let Some(p) = unknown {
+            return Err(FakeCiError::Config(format!(
+                "Unknown placeholder ${{{}}} in the image name \"{}\" (available: branch, commit, commit_short, timestamp)",
+                p, name
+            )));
+        }
+        self.name = Some(rendered.to_string());
+        Ok(())
+    }
+}
+
+fn skip_tags_default() -> Vec<String> {
+    vec!["[skip ci]".to_string(), "[ci skip]".to_string()]
+}
+
+fn is_default_skip_tags(tags: &[String]) -> bool {
+    tags == skip_tags_default()
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    t == &T::default()
+}
+
+/// Serializes `map` with its keys sorted, so that the output is stable
+pub(crate) fn sorted<S: Serializer>(
+    map: &HashMap<String, String>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(s)
+}
+
+/// Returns the JSON schema of [FakeCIRepoConfig], for editors to validate `.fakeci.yml` files
+pub fn json_schema() -> String {
+    serde_json::to_string_pretty(&schema_for!(FakeCIRepoConfig))
+        .expect("could not serialize the config schema")
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+/// Formats a `.fakeci.yml` canonically: keys in a stable order, defaults omitted, and steps
+/// running a single command shortened. Comments are lost, and anchors expanded.
+/// Errors if the formatted config wouldn't mean the same as `s`.
+pub fn format_config(s: &str) -> Result<String, FakeCiError> {
+    let parse = |s: &str| -> Result<FakeCIRepoConfig, FakeCiError> {
+        serde_yaml::from_str(s)
+            .map_err(|e| FakeCiError::Config(format!("Could not parse the config: {}", e)))
+    };
+    let conf = parse(s)?;
+    let formatted = serde_yaml::to_string(&conf)
+        .map_err(|e| FakeCiError::Config(format!("Could not serialize the config: {}", e)))?;
+    if parse(&formatted)? != conf {
+        return Err(FakeCiError::Config(
+            "The formatted config would not mean the same; leaving it as is".to_string(),
+        ));
+    }
+    Ok(formatted)
+}
+
+/// Comments of the [starter config](starter_config), each added at the end of the first line
+/// starting with its key
+const STARTER_COMMENTS: &[(&str, &str)] = &[
+    ("pipeline:", "the jobs, run one after the other"),
+    (
+        "- name:",
+        "a name for the job, as shown in the summary & notifications",
+    ),
+    (
+        "image:",
+        "the docker image the job runs in. Or `runner: host`, to run on the host",
+    ),
+    (
+        "steps:",
+        "run in order: the job stops at the first failing command",
+    ),
+    (
+        "exec:",
+        "shell commands, run from the repository, mounted as /code",
+    ),
+    ("env:", "envvars of the job's commands"),
+    (
+        "secrets:",
+        "secrets the job may use, as envvars. Their values are given by the watcher's config",
+    ),
+];
+
+/// A commented `.fakeci.yml` to start from: a single job, running a step in `ubuntu`, with an
+/// envvar & a secret
+pub fn starter_config() -> String {
+    let conf: FakeCIRepoConfig = serde_json::from_value(serde_json::json!({
+        "pipeline": [{
+            "name": "greet",
+            "image": "ubuntu",
+            "steps": [{
+                "name": "greets the greeted",
+                "exec": ["echo \"$GREETER says: Hello, $GREETED!\""],
+            }],
+            "env": {"GREETED": "world"},
+            "secrets": ["GREETER"],
+        }],
+    }))
+    .expect("invalid starter config");
+    let yaml = serde_yaml::to_string(&conf).expect("could not serialize the starter config");
+    let mut comments = STARTER_COMMENTS.to_vec();
+    let mut s = "# The pipeline of this repository. Check it with `fake-ci lint`, and run it with `fake-ci run`\n".to_string();
+    for line in yaml.lines() {
+        s.push_str(line);
+        if let Some(i) = comments
+            .iter()
+            .position(|(key, _)| line.trim_start().starts_with(key))
+        {
+            s.push_str(" # ");
+            s.push_str(comments.remove(i).1);
+        }
+        s.push('\n');
+    }
+    s
+}
+
+/// Writes the [starter config](starter_config) to `path`. Errors if it exists, unless `force`
+pub fn init_config(path: &Path, force: bool) -> Result<(), FakeCiError> {
+    if path.exists() && !force {
+        return Err(FakeCiError::Config(format!(
+            "{} already exists; use --force to overwrite it",
+            path.display()
+        )));
+    }
+    std::fs::write(path, starter_config())?;
+    Ok(())
+}
+
+impl FakeCIRepoConfig {
+    /// Errors if fake-ci's `current` version is older than the configured `min_version`
+    pub fn check_min_version(&self, current: &str) -> Result<(), FakeCiError> {
+        let min = match &self.min_version {
+            None => return Ok(()),
+            Some(m) => Version::parse(m)
+                .map_err(|e| FakeCiError::Config(format!("Invalid min_version {}: {}", m, e)))?,
+        };
+        let current = Version::parse(current)
+            .map_err(|e| FakeCiError::Config(format!("Invalid version {}: {}", current, e)))?;
+        if current < min {
+            return Err(FakeCiError::Config(format!(
+                "This config requires fake-ci {} or newer, but this is fake-ci {}",
+                min, current
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns this config, with its [variables](FakeCIRepoConfig::variables) replaced by their
+    /// values. Errors if one isn't defined
+    pub fn resolve_variables(&self) -> Result<FakeCIRepoConfig, FakeCiError> {
+        let invalid = |e: serde_yaml::Error| {
+            FakeCiError::Config(format!("Could not resolve the variables: {}", e))
+        };
+        let mut value = serde_yaml::to_value(self).map_err(invalid)?;
+        if let Value::Mapping(map) = &mut value {
+            map.remove(&Value::from("variables"));
+        }
+        interpolate(&mut value, &self.variables)?;
+        let mut resolved: FakeCIRepoConfig = serde_yaml::from_value(value).map_err(invalid)?;
+        resolved.variables = self.variables.clone();
+        Ok(resolved)
+    }
+
+    /// Returns this config, with the jobs setting [images](FakeCIJob::images) replaced by one
+    /// job per image, named after its [label](Image::label), in place. Errors if a job sets both
+    /// `image` & `images`
+    pub fn expand_images(mut self) -> Result<FakeCIRepoConfig, FakeCiError> {
+        let invalid = |e: serde_yaml::Error| {
+            FakeCiError::Config(format!("Could not expand the images: {}", e))
+        };
+        let mut pipeline = vec![];
+        for job in self.pipeline.drain(..) {
+            if job.images.is_empty() {
+                pipeline.push(job);
+                continue;
+            }
+            if job.image.is_some() {
+                return Err(FakeCiError::Config(format!(
+                    "Job \"{}\" sets both image & images",
+                    job.name
+                )));
+            }
+            let value = serde_yaml::to_value(&job).map_err(invalid)?;
+            for image in &job.images {
+                let mut expanded = value.clone();
+                if let Value::Mapping(map) = &mut expanded {
+                    map.remove(&Value::from("images"));
+                    map.insert(
+                        Value::from("image"),
+                        serde_yaml::to_value(image).map_err(invalid)?,
+                    );
+                    map.insert(
+                        Value::from("name"),
+                        Value::from(format!("{} [{}]", job.name, image.label())),
+                    );
+                }
+                pipeline.push(serde_yaml::from_value(expanded).map_err(invalid)?);
+            }
+        }
+        self.pipeline = pipeline;
+        Ok(self)
+    }
+
+    /// Returns this config, with the [timeout](FakeCIDefaultConfig::timeout),
+    /// [retry](FakeCIDefaultConfig::retry), [heartbeat](FakeCIDefaultConfig::heartbeat_interval)
+    /// & [artifacts size](FakeCIDefaultConfig::max_artifact_size) defaults given to the jobs &
+    /// steps not setting theirs
+    pub fn apply_defaults(mut self) -> FakeCIRepoConfig {
+        let (timeout, retry, heartbeat_interval, max_artifact_size) = match &self.default {
+            Some(d) => (
+                d.timeout,
+                d.retry,
+                d.heartbeat_interval,
+                d.max_artifact_size,
+            ),
+            None => return self,
+        };
+        for job in &mut self.pipeline {
+            job.timeout = job.timeout.or(timeout);
+            job.heartbeat_interval = job.heartbeat_interval.or(heartbeat_interval);
+            job.max_artifact_size = job.max_artifact_size.or(max_artifact_size);
+            for step in &mut job.steps {
+                step.retry = step.retry.or(retry);
+            }
+        }
+        self
+    }
+
+    /// Returns this config, with the names of the images to [build](FakeCIDockerBuild::name)
+    /// rendered for the pipeline run in `ctx`, started at `start`. Errors on unknown placeholders
+    pub fn render_image_names(
+        mut self,
+        ctx: &ExecutionContext,
+        start: DateTime<Utc>,
+    ) -> Result<FakeCIRepoConfig, FakeCiError> {
+        let images = self
+            .default
+            .iter_mut()
+            .filter_map(|d| d.image.as_mut())
+            .chain(self.pipeline.iter_mut().filter_map(|j| j.image.as_mut()));
+        for image in images {
+            if let Image::Build(build) = image {
+                build.render_name(ctx, start)?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Returns this config, with only the jobs whose names match one of the `globs`, and the
+    /// jobs whose [artifacts they need](FakeCIJob::needs_artifacts_from). Errors if a glob is
+    /// invalid, or matches no job. No glob keeps all the jobs
+    pub fn only_jobs(mut self, globs: &[String]) -> Result<FakeCIRepoConfig, FakeCiError> {
+        if globs.is_empty() {
+            return Ok(self);
+        }
+        let mut wanted = HashSet::new();
+        for g in globs {
+            let pattern = glob::Pattern::new(g)
+                .map_err(|e| FakeCiError::Config(format!("Invalid job glob {}: {}", g, e)))?;
+            let matching: Vec<String> = self
+                .pipeline
+                .iter()
+                .filter(|j| pattern.matches(&j.name))
+                .map(|j| j.name.to_string())
+                .collect();
+            if matching.is_empty() {
+                return Err(FakeCiError::Config(format!("No job matches {}", g)));
+            }
+            wanted.extend(matching);
+        }
+        // pull in the dependencies, then theirs
+        let mut todo: Vec<String> = wanted.iter().cloned().collect();
+        while let Some(name) = todo.pop() {
+            let job = self.pipeline.iter().find(|j| j.name == name);
+            for dep in job.iter().flat_map(|j| &j.needs_artifacts_from) {
+                if wanted.insert(dep.to_string()) {
+                    todo.push(dep.to_string());
+                }
+            }
+        }
+        self.pipeline.retain(|j| wanted.contains(&j.name));
+        Ok(self)
+    }
+
+    /// The jobs of the pipeline, grouped by [stage](FakeCIRepoConfig::stages), in order. Without
+    /// stages, each job is a group of its own. Errors if a job has no stage, or an unknown one,
+    /// or [needs the artifacts](FakeCIJob::needs_artifacts_from) of a job of its stage or a later one
+    pub fn job_stages(&self) -> Result<Vec<Vec<&FakeCIJob>>, FakeCiError> {
+        if self.stages.is_empty() {
+            if let Some(job) = self.pipeline.iter().find(|j| j.stage.is_some()) {
+                return Err(FakeCiError::Config(format!(
+                    "Job \"{}\" has a stage, but the pipeline has no stages",
+                    job.name
+                )));
+            }
+            return Ok(self.pipeline.iter().map(|j| vec![j]).collect());
+        }
+        let stage_of = |job: &FakeCIJob| -> Result<usize, FakeCiError> {
+            let stage = job.stage.as_ref().ok_or_else(|| {
+                FakeCiError::Config(format!(
+                    "Job \"{}\" has no stage; pick one of {}",
+                    job.name,
+                    self.stages.join(", ")
+                ))
+            })?;
+            self.stages.iter().position(|s| s == stage).ok_or_else(|| {
+                FakeCiError::Config(format!(
+                    "Job \"{}\" is in stage {}, which isn't in the stages: {}",
+                    job.name,
+                    stage,
+                    self.stages.join(", ")
+                ))
+            })
+        };
+        let mut stages: Vec<Vec<&FakeCIJob>> = self.stages.iter().map(|_| vec![]).collect();
+        for job in &self.pipeline {
+            let stage = stage_of(job)?;
+            for needed in &job.needs_artifacts_from {
+                if let Some(upstream) = self.pipeline.iter().find(|j| &j.name == needed) {
+                    if stage_of(upstream)? >= stage {
+                        return Err(FakeCiError::Config(format!(
+                            "Job \"{}\" needs the artifacts of job \"{}\", which isn't in an earlier stage",
+                            job.name, needed
+                        )));
+                    }
+                }
+            }
+            stages[stage].push(job);
+        }
+        Ok(stages)
+    }
+
+    /// Checks what can be without running the pipeline: fake-ci's `current` version against the
+    /// [min_version](FakeCIRepoConfig::min_version), the [variables](FakeCIRepoConfig::variables),
+    /// the [images](FakeCIJob::images), the [min_pass_ratio](FakeCIRepoConfig::min_pass_ratio) &
+    /// the [stages](FakeCIRepoConfig::job_stages)
+    pub fn validate(&self, current: &str) -> Result<(), FakeCiError> {
+        self.check_min_version(current)?;
+        self.check_min_pass_ratio()?;
+        self.resolve_variables()?.expand_images()?.job_stages()?;
+        Ok(())
+    }
+
+    /// Errors if the [min_pass_ratio](FakeCIRepoConfig::min_pass_ratio) isn't between 0 & 1
+    pub fn check_min_pass_ratio(&self) -> Result<(), FakeCiError> {
+        match self.min_pass_ratio {
+            Some(r) if !(0.0..=1.0).contains(&r) => Err(FakeCiError::Config(format!(
+                "min_pass_ratio must be between 0 & 1, got {}",
+                r
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Eq, PartialEq)]
 /// Represents an image we must build ourselves
 pub struct FakeCIDockerBuild {
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// Optional path to the dockerfile. Will use Dockerfile if not specified
     pub dockerfile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// Optional context. Default: .
     pub context: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// List of build args to pass to docker build
     pub build_args: Option<Vec<String>>,
-    /// Name of the image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Name of the image. It can use `${branch}`, `${commit}`, `${commit_short}` and
+    /// `${timestamp}`, replaced [once the commit is known](FakeCIRepoConfig::render_image_names).
+    /// Ex: `registry.example.org/app:${branch}-${commit_short}`. Default: a random name
     pub name: Option<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "is_default")]
     /// Should the image be privileged?
     pub privileged: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Should each step of the build be timed, in the job's
+    /// [build timings](crate::JobResult::build_timings)? Needs BuildKit, the default builder
+    /// of docker since 23.0, which then prints its progress as plain text
+    pub timings: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Names of the executor's secrets to expose to the build, without baking them in the image:
+    /// a `RUN --mount=type=secret,id=NAME` instruction finds the secret `NAME` in
+    /// `/run/secrets/NAME`. Needs BuildKit
+    pub secrets: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Eq, PartialEq)]
+// a name with build options is an image to build
+#[serde(deny_unknown_fields)]
 /// Represents a docker image, with some options
 pub struct FakeCIDockerImage {
     /// Name of the docker image Ex: ubuntu
     pub name: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "is_default")]
     /// Should the image run in privileged mode?
     pub privileged: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// The credentials a [job](FakeCIJob::registry_auth) logs into a registry with, to pull its image
+pub struct RegistryAuth {
+    /// The registry to log into. Ex: `registry.example.org`
+    pub registry: String,
+    /// The user to log in as. Can hold secrets, as `${secret.NAME}`
+    pub username: String,
+    /// The name of the secret holding the password, or token
+    pub password_secret: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Eq, PartialEq)]
 #[serde(untagged)]
 /// A docker image to use to run the [job](FakeCIJob)
 pub enum Image {
@@ -104,7 +1362,7 @@ pub enum Image {
     Build(FakeCIDockerBuild),
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Eq, PartialEq)]
 /// Represents a Job. Serializes to:
 /// ```yaml
 /// name: say hello  # a name for this job.
@@ -123,22 +1381,183 @@ pub enum Image {
 pub struct FakeCIJob {
     /// The job's name
     pub name: String,
-    /// An optional image definition
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// An optional image definition. Its name can hold secrets, as `${secret.NAME}`:
+    /// `registry.example.org/${secret.REGISTRY_USER}/app:1.0`. They are masked in the logs
     pub image: Option<Image>,
-    /// A list of steps to execute
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Run the job once per image, instead of in a single [image](FakeCIJob::image): each run
+    /// is a job of its own, named after its image. Ex: `[debian:12, alpine:3.19]` gives the
+    /// jobs `test [debian:12]` & `test [alpine:3.19]`. See [FakeCIRepoConfig::expand_images]
+    pub images: Vec<Image>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Platform of the image to build or pull. Ex: `linux/amd64`. Overrides the default one
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Platforms to try in turn, until the container starts: the image may not be published
+    /// for the preferred one. Ex: `[linux/arm64, linux/amd64]`. Overrides `platform`
+    pub platforms: Vec<String>,
+    #[serde(
+        deserialize_with = "deserialize_steps",
+        serialize_with = "serialize_steps"
+    )]
+    #[schemars(with = "Vec<StepSpec>")]
+    /// A list of steps to execute. A plain string is a step running that single command
     pub steps: Vec<FakeCIStep>,
-    #[serde(default)]
+    #[serde(
+        default,
+        skip_serializing_if = "Env::is_empty",
+        serialize_with = "sorted"
+    )]
     /// Environment to pass to the steps
     pub env: Env,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     /// Secrets to pass to the steps. Note: actual secret definition is left to inbound interfaces
     pub secrets: Vec<String>,
-    #[serde(default)]
-    /// Volumes we should mount. Note: the repository is always mounted as /code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Volumes we should mount. Note: the repository is mounted as /code, unless
+    /// [mount_source](FakeCIJob::mount_source) is false. Like the [image](FakeCIJob::image),
+    /// they can hold `${secret.NAME}`
     pub volumes: Vec<String>,
+    #[serde(
+        default = "mount_source_default",
+        skip_serializing_if = "is_mount_source_default"
+    )]
+    /// Should the repository be mounted as /code, and used as working directory? Default: true
+    pub mount_source: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Arguments passed as-is to `docker run`, for the options we don't handle ourselves.
+    /// Ex: `--shm-size=256m`.
+    ///
+    /// **Beware**: nothing is checked; these can be used to escape the container
+    /// (`--volume=/:/host`, `--pid=host`…)
+    pub extra_docker_args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Entries to add to the container's `/etc/hosts`, as `HOST:IP`, given to
+    /// `docker run --add-host`. Ex: `db:127.0.0.1`, or `api:host-gateway` to reach the host
+    pub extra_hosts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Credentials to pull the job's image with. They are only given to this job: it logs in
+    /// before starting its container, in a config of its own, and out once it is done
+    pub registry_auth: Option<RegistryAuth>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Where to run the steps. Default: in a docker container.
+    ///
+    /// **Beware**: [Runner::Host] jobs run with fake-ci's own user & permissions, with no
+    /// isolation at all.
+    pub runner: Runner,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Files to keep once the job is done, as glob patterns relative to the repository
+    /// (`/code` in the container). Ex: `target/release/fake-ci`. They are copied to the
+    /// [artifacts dir](crate::LaunchOptions::artifacts_dir), keeping their directory structure.
+    pub artifacts: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// If set, the [artifacts](FakeCIJob::artifacts) are also packed in a `.tar.gz` at this path,
+    /// relative to the artifacts dir. Needs the `artifacts` feature.
+    pub artifacts_archive: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The most the [artifacts](FakeCIJob::artifacts) may weigh, all together, in bytes. Past
+    /// it, none of them is saved, and the job's logs tell why: a glob matching too much can't
+    /// fill the disk. Overrides the [default one](FakeCIDefaultConfig::max_artifact_size).
+    /// Default: no limit
+    pub max_artifact_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// A `.env` file the job writes, relative to the repository, as `KEY=VALUE` lines. Ex:
+    /// `build.env`. Once the job succeeded, its variables are given to the jobs running after it,
+    /// in the later stages: they override these jobs' [env](FakeCIJob::env), but not their
+    /// secrets. Like GitLab's `artifacts:reports:dotenv`
+    pub dotenv_artifact: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Names of earlier jobs whose [artifacts](FakeCIJob::artifacts) this job needs. They are
+    /// copied into the repository before the job runs; the job fails if one of these jobs
+    /// didn't succeed.
+    pub needs_artifacts_from: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Where the [needed artifacts](FakeCIJob::needs_artifacts_from) are copied, relative to
+    /// the repository. Default: its root, so that they are found where they were produced
+    pub needed_artifacts_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// A `.env` file to load instead of the [default one](FakeCIDefaultConfig::env_file),
+    /// relative to the repository root
+    pub env_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Keep at most this many lines of logs; the oldest ones are dropped first
+    pub max_log_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Keep at most this many bytes of logs; the oldest lines are dropped first
+    pub max_log_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// If the job fails, keep its container around to inspect it, instead of removing it.
+    /// Overrides the [default one](FakeCIDefaultConfig::keep_on_failure).
+    ///
+    /// Kept containers are never removed by fake-ci: `docker rm -f` them once done.
+    pub keep_on_failure: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The shell running the commands of the steps, with its flags. They are passed to it with
+    /// `-c`, or `-Command` for `powershell` & `pwsh`. Ex: `bash -o pipefail`. Overrides the [default one](FakeCIDefaultConfig::shell).
+    /// Default: `sh` in containers, `bash` on the host
+    pub shell: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "sorted"
+    )]
+    /// Secrets to mount as read-only files, for tools wanting them on disk: secret name ->
+    /// absolute path in the container. Their contents are masked in the logs.
+    /// Not available to jobs running on the host.
+    pub secret_files: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// If true, this job failing doesn't fail the pipeline, and it isn't counted in the
+    /// [min_pass_ratio](FakeCIRepoConfig::min_pass_ratio)
+    pub allow_failure: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// If true, the notifiers don't count this job when telling if the pipeline succeeded,
+    /// though they still list how it went. Unlike [allow_failure](FakeCIJob::allow_failure),
+    /// its failure still fails the pipeline. Ex: an experimental benchmark
+    pub notify_ignore: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// How long the job may run, in seconds. A command still running then is killed (with its
+    /// container, for the jobs run in one), and the job fails, skipping the next ones.
+    /// Overrides the [default one](FakeCIDefaultConfig::timeout). Default: no limit
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// While a command runs, logs that its step is still running every this many seconds, so
+    /// that a silent command can't be mistaken for a hung fake-ci. Overrides the
+    /// [default one](FakeCIDefaultConfig::heartbeat_interval). Default: no heartbeat
+    pub heartbeat_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The [stage](FakeCIRepoConfig::stages) the job runs in. Required when the pipeline has
+    /// stages, and forbidden otherwise
+    pub stage: Option<String>,
+}
+
+fn mount_source_default() -> bool {
+    true
+}
+
+fn is_mount_source_default(mount_source: &bool) -> bool {
+    *mount_source == mount_source_default()
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// Where a [job](FakeCIJob)'s steps are run
+pub enum Runner {
+    #[default]
+    /// In a docker container, created from the job's image
+    Docker,
+    /// Directly on the host, with `bash -c`, in the repository's directory.
+    /// Same as `image: local`.
+    Host,
 }
 
 impl FakeCIJob {
+    /// Should this job run directly on the host? True for `runner: host` and `image: local`
+    pub fn runs_on_host(&self) -> bool {
+        self.runner == Runner::Host
+            || matches!(&self.image, Some(Image::Existing(i)) if i == "local")
+    }
+
     /// Generates a random, valid, container name according to the job's name
     pub fn generate_container_name(&self) -> String {
         let valid_bytes = self
@@ -157,19 +1576,255 @@ impl FakeCIJob {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Eq, PartialEq)]
 /// a [job](FakeCIJob) step. Serializes to the following:
 /// ```yaml
 /// name: step 1 # Optional, will have an auto-generated sequential name if absent
-/// exec: # a list of shell commands to execute. Each one will be executed in its own `docker start°
+/// exec: # a list of shell commands to execute. Each one will be executed in its own `docker exec`
 ///   - say hello
 ///   - eat pie together
+/// workdir: frontend # Optional, where to run the commands, relative to the job's working directory
+/// allow_failure: true # Optional, a failure of this step doesn't fail the job. Default: false
+/// retry: 2 # Optional, how many more times to run this step if it fails. Default: 0
+/// retry_on: # Optional, only retry the failures returning one of these codes, or printing one of these
+///   exit_codes: [75]
+///   stderr: ["connection reset"]
+/// expect_exit: 1 # Optional, the exit code the commands must return. Default: 0
 /// ```
 pub struct FakeCIStep {
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// An arbitrary, optional, name
     pub name: Option<String>,
     /// A list of shell commands to execute for this step
     pub exec: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Directory to run the commands in, relative to the job's working directory
+    pub workdir: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// If this step failing doesn't fail the job: its remaining commands are skipped, and the
+    /// next steps run anyway
+    pub allow_failure: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Run the commands of this step all at once, rather than one after the other. Their
+    /// outputs are logged in the order of the commands, and the step fails if any of them does
+    pub parallel: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Run the commands of this step in a privileged container. See [docker](FakeCIStep::docker)
+    pub privileged: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Give the commands of this step the docker socket of the host. As a running container's
+    /// privileges can't change, they run in a sidecar container instead: from the same image,
+    /// with the same env & volumes, and removed once they're done. What the previous steps
+    /// wrote outside the volumes & the source dir isn't there
+    pub docker: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// How many more times to run this step if it fails, from its first command. Overrides the
+    /// [default one](FakeCIDefaultConfig::retry). Default: 0
+    pub retry: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Which failures are [retried](FakeCIStep::retry): transient ones, such as a network error,
+    /// rather than a compile error. Default: all of them
+    pub retry_on: RetryOn,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// The exit code the commands of this step must return for it to succeed, to check that a
+    /// command fails as intended. It is only retried when they return another one. Default: 0
+    pub expect_exit: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+/// The failures of a [step](FakeCIStep::retry_on) worth retrying: those returning one of the
+/// exit codes, or whose stderr matches one of the regexes. If neither is set, all of them
+pub struct RetryOn {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Exit codes of transient failures. Ex: `75`, `EX_TEMPFAIL`
+    pub exit_codes: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Regexes matching the stderr of transient failures. Ex: `(?i)connection reset`
+    pub stderr: Vec<StderrPattern>,
+}
+
+#[derive(Debug, Clone)]
+/// A [retry_on](RetryOn::stderr) regex, compiled as the config is loaded: an invalid one fails
+/// the config, rather than the step it would retry
+pub struct StderrPattern(Regex);
+
+impl StderrPattern {
+    /// Compiles `pattern`. Errors if it isn't a valid regex
+    pub fn new(pattern: &str) -> Result<Self, FakeCiError> {
+        Self::compile(pattern).map_err(FakeCiError::Config)
+    }
+
+    /// Compiles `pattern`, with the error as a message, for serde to report
+    fn compile(pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern)
+            .map(StderrPattern)
+            .map_err(|e| format!("Invalid retry_on regex {}: {}", pattern, e))
+    }
+
+    /// The regex, as written in the config
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl PartialEq for StderrPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for StderrPattern {}
+
+impl Serialize for StderrPattern {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for StderrPattern {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        StderrPattern::compile(&String::deserialize(d)?).map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for StderrPattern {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl RetryOn {
+    /// Should a failure, which returned `code` and printed `stderr`, be retried?
+    pub fn matches(&self, code: Option<i32>, stderr: &str) -> bool {
+        if self.exit_codes.is_empty() && self.stderr.is_empty() {
+            return true;
+        }
+        code.is_some_and(|c| self.exit_codes.contains(&c))
+            || self.stderr.iter().any(|p| p.0.is_match(stderr))
+    }
+}
+
+impl FakeCIStep {
+    /// Did a command of this step return what it [should](FakeCIStep::expect_exit)? Commands
+    /// killed by a signal, with no exit `code`, never do
+    pub fn expected_exit(&self, code: Option<i32>) -> bool {
+        code == Some(self.expect_exit.unwrap_or(0))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+/// How a [step](FakeCIStep) can be written
+enum StepSpec {
+    /// A single command, in an unnamed step. Ex: "make build"
+    Command(String),
+    /// A complete step
+    Full(FakeCIStep),
+}
+
+impl From<StepSpec> for FakeCIStep {
+    fn from(spec: StepSpec) -> Self {
+        match spec {
+            StepSpec::Command(command) => FakeCIStep {
+                name: None,
+                exec: vec![command],
+                workdir: None,
+                allow_failure: false,
+                parallel: false,
+                privileged: false,
+                docker: false,
+                retry: None,
+                retry_on: RetryOn::default(),
+                expect_exit: None,
+            },
+            StepSpec::Full(step) => step,
+        }
+    }
+}
+
+/// Serializes the steps, using the [shortcut](StepSpec::Command) for those running a single
+/// command, with nothing else set
+fn serialize_steps<S: Serializer>(steps: &[FakeCIStep], s: S) -> Result<S::Ok, S::Error> {
+    let mut seq = s.serialize_seq(Some(steps.len()))?;
+    for step in steps {
+        match step {
+            FakeCIStep {
+                name: None,
+                exec,
+                workdir: None,
+                allow_failure: false,
+                parallel: false,
+                privileged: false,
+                docker: false,
+                retry: None,
+                retry_on,
+                expect_exit: None,
+            } if exec.len() == 1 && is_default(retry_on) => seq.serialize_element(&exec[0])?,
+            step => seq.serialize_element(step)?,
+        }
+    }
+    seq.end()
+}
+
+/// Reads the steps one by one, rather than as untagged [StepSpec]s: the error of an invalid
+/// step, such as a bad [retry_on](RetryOn::stderr) regex, then says what's wrong with it, rather
+/// than only that it is neither a command nor a step
+fn deserialize_steps<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<FakeCIStep>, D::Error> {
+    let values: Vec<Value> = Vec::deserialize(d)?;
+    values
+        .into_iter()
+        .map(|v| {
+            let spec = match v {
+                Value::String(command) => StepSpec::Command(command),
+                v => StepSpec::Full(FakeCIStep::deserialize(v).map_err(serde::de::Error::custom)?),
+            };
+            Ok(FakeCIStep::from(spec))
+        })
+        .collect()
+}
+
+#[derive(Debug, Eq, PartialEq)]
+/// How an image reference designates its version
+pub enum ImageVersion {
+    /// Pinned by digest. Ex: `ubuntu@sha256:45b23dee08af…`
+    Digest,
+    /// A tag. Ex: `ubuntu:22.04`
+    Tag(String),
+    /// No version at all, implicitly `latest`. Ex: `ubuntu`
+    None,
+}
+
+impl ImageVersion {
+    /// Parses the version out of an image reference, such as `registry:5000/ubuntu:22.04`
+    pub fn of(reference: &str) -> Self {
+        if reference.contains('@') {
+            return ImageVersion::Digest;
+        }
+        // a colon before the last slash is a registry port, not a tag
+        let name = reference.rsplit('/').next().unwrap_or(reference);
+        match name.rsplit_once(':') {
+            Some((_, tag)) => ImageVersion::Tag(tag.to_string()),
+            None => ImageVersion::None,
+        }
+    }
+
+    /// Does this version move over time? True for no version, and `latest`
+    pub fn is_floating(&self) -> bool {
+        match self {
+            ImageVersion::Digest => false,
+            ImageVersion::Tag(t) => t == "latest",
+            ImageVersion::None => true,
+        }
+    }
 }
 
 impl Image {
@@ -181,6 +1836,19 @@ impl Image {
             Image::Build(b) => b.privileged,
         }
     }
+    /// A short name telling the image apart: its name, or the dockerfile of an image built
+    /// without one
+    pub fn label(&self) -> String {
+        match self {
+            Image::Build(FakeCIDockerBuild {
+                name: None,
+                dockerfile,
+                ..
+            }) => dockerfile.as_deref().unwrap_or("Dockerfile").to_string(),
+            image => image.get_name().unwrap_or_default(),
+        }
+    }
+
     /// Returns the image's name according to variants
     pub fn get_name(&self) -> Option<String> {
         match self {
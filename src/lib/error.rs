@@ -0,0 +1,50 @@
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+/// What can go wrong when using fake-ci as a library. Internally, errors are [anyhow::Error]s,
+/// converted to this when they reach the public API.
+pub enum FakeCiError {
+    /// The configuration is missing or invalid: malformed yaml, unknown job, undefined secret…
+    #[error("configuration error: {0}")]
+    Config(String),
+    /// The repository has no `.fakeci.yml`: there is nothing to run. Not a failure of the
+    /// pipeline, but the repository not using fake-ci, at least on this branch
+    #[error("no .fakeci.yml found in {0}")]
+    NoConfig(String),
+    /// A git operation failed, such as listing refs or cloning
+    #[error("git error: {0}")]
+    Git(String),
+    /// A docker operation failed, or docker is not installed
+    #[error("docker error: {0}")]
+    Docker(String),
+    /// Docker is installed, but its daemon can't be reached: it is stopped, or its socket is
+    /// not accessible
+    #[error("Docker daemon is not reachable — is it running?")]
+    DaemonUnreachable,
+    /// A filesystem operation failed
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// A notification could not be sent
+    #[error("notification error: {0}")]
+    Notification(String),
+    /// An operation took too long, and was aborted
+    #[error("timed out: {0}")]
+    Timeout(String),
+}
+
+impl FakeCiError {
+    /// Converts an internal error. If it is (or wraps) a [FakeCiError] or an [io::Error], its
+    /// variant is kept; otherwise, `fallback` is used.
+    pub(crate) fn classify(e: anyhow::Error, fallback: fn(String) -> FakeCiError) -> FakeCiError {
+        let e = match e.downcast::<FakeCiError>() {
+            Ok(f) => return f,
+            Err(e) => e,
+        };
+        match e.downcast::<io::Error>() {
+            Ok(io) => FakeCiError::Io(io),
+            Err(e) => fallback(format!("{:#}", e)),
+        }
+    }
+}
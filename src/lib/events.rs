@@ -0,0 +1,239 @@
+use std::io::Write;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use pretty_assertions::assert_eq;
+    use serde_json::Value;
+
+    use crate::events::{Event, JsonEvents};
+    use crate::tests::ConfigOnlyVcs;
+    use crate::utils::tests::deser_yaml;
+    use crate::{execute_config, LaunchOptions};
+
+    /// A writer whose content stays readable once it's been given away
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_events() {
+        let out = Shared::default();
+        let conf = deser_yaml(
+            "pipeline:
+  - name: build
+    runner: host
+    steps:
+      - name: compile
+        exec: [\"echo compiled\"]
+  - name: test
+    runner: host
+    steps:
+      - \"false\"",
+        )
+        .expect("could not deserialize config");
+        let opts = LaunchOptions {
+            repo_name: "events".to_string(),
+            branch: "main".to_string(),
+            observer: Some(Arc::new(JsonEvents::new(out.clone()))),
+            ..Default::default()
+        };
+        execute_config(conf, &opts, &ConfigOnlyVcs("", ""), &[]).expect("could not execute config");
+
+        let written = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<Value> = written
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("not a JSON line"))
+            .collect();
+        assert!(events.iter().all(|e| e["timestamp"].is_string()));
+        let summary: Vec<(&str, &str, &str)> = events
+            .iter()
+            .map(|e| {
+                (
+                    e["event"].as_str().unwrap(),
+                    e["job"].as_str().unwrap_or(""),
+                    e["status"].as_str().unwrap_or(""),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("pipeline_started", "", ""),
+                ("job_started", "build", ""),
+                ("step_started", "build", ""),
+                ("step_output", "build", ""),
+                ("step_finished", "build", "success"),
+                ("job_finished", "build", "success"),
+                ("job_started", "test", ""),
+                ("step_started", "test", ""),
+                ("step_finished", "test", "failure"),
+                ("job_finished", "test", "failure"),
+                ("pipeline_finished", "", "failure"),
+            ]
+        );
+        assert_eq!(events[0]["repo"], "events");
+        assert_eq!(events[0]["branch"], "main");
+        assert_eq!(events[2]["step"], "compile");
+        assert_eq!(events[3]["stream"], "stdout");
+        assert_eq!(events[3]["output"], "compiled\n");
+        // unnamed steps are identified by their index
+        assert_eq!(events[7]["step"], "0");
+        assert!(events[5]["duration_ms"].is_i64());
+    }
+
+    #[test]
+    fn serialization() {
+        let event = Event::StepFinished {
+            job: "build".to_string(),
+            step: "compile".to_string(),
+            status: "success",
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "event": "step_finished",
+                "job": "build",
+                "step": "compile",
+                "status": "success"
+            })
+        );
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+/// Something that happened while running a pipeline, told to the [observer](Observe) right
+/// away. Statuses are `success`, `failure`, `allowed_failure` or `skipped`. Steps are identified
+/// by their name, or their index in the job if they have none.
+pub enum Event {
+    /// The pipeline is about to run, on this commit
+    PipelineStarted {
+        /// The repository's name
+        repo: String,
+        /// The branch built
+        branch: String,
+        /// The hash of the commit built
+        commit: String,
+    },
+    /// A job is about to run
+    JobStarted {
+        /// The job's name
+        job: String,
+    },
+    /// A step is about to run
+    StepStarted {
+        /// The job's name
+        job: String,
+        /// The step's name
+        step: String,
+    },
+    /// A command of a step has written something. Secrets are masked
+    StepOutput {
+        /// The job's name
+        job: String,
+        /// The step's name
+        step: String,
+        /// `stdout` or `stderr`
+        stream: &'static str,
+        /// What was written
+        output: String,
+    },
+    /// A step has run
+    StepFinished {
+        /// The job's name
+        job: String,
+        /// The step's name
+        step: String,
+        /// How it went
+        status: &'static str,
+    },
+    /// A job has run, or could not
+    JobFinished {
+        /// The job's name
+        job: String,
+        /// How it went
+        status: &'static str,
+        /// How long it took
+        duration_ms: i64,
+    },
+    /// The pipeline has run, or was skipped
+    PipelineFinished {
+        /// The repository's name
+        repo: String,
+        /// The branch built
+        branch: String,
+        /// The hash of the commit built
+        commit: String,
+        /// How it went
+        status: &'static str,
+        /// How long it took
+        duration_ms: i64,
+    },
+}
+
+/// Is told about the [events](Event) of a pipeline as they happen, to follow it from outside.
+/// Ex: [JsonEvents]
+pub trait Observe: Send + Sync {
+    /// Called for each event, in order. Can't fail the pipeline: failures should be logged
+    fn observe(&self, event: &Event);
+}
+
+/// Tells `observer`, if any, about the event built by `event`. It's only built when needed
+pub(crate) fn emit(observer: Option<&dyn Observe>, event: impl FnOnce() -> Event) {
+    if let Some(o) = observer {
+        o.observe(&event());
+    }
+}
+
+#[derive(Serialize)]
+struct Stamped<'a> {
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// Writes each [Event] as a line of JSON, with its `timestamp`. Ex:
+/// ```json
+/// {"timestamp":"2021-11-28T12:00:00Z","event":"job_started","job":"build"}
+/// ```
+pub struct JsonEvents<W: Write + Send>(Mutex<W>);
+
+impl<W: Write + Send> JsonEvents<W> {
+    /// Writes the events to `out`
+    pub fn new(out: W) -> Self {
+        JsonEvents(Mutex::new(out))
+    }
+}
+
+impl<W: Write + Send> Observe for JsonEvents<W> {
+    fn observe(&self, event: &Event) {
+        let stamped = Stamped {
+            timestamp: Utc::now(),
+            event,
+        };
+        let mut out = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let written = serde_json::to_writer(&mut *out, &stamped)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(writeln!(out)?))
+            .and_then(|_| Ok(out.flush()?));
+        if let Err(e) = written {
+            warn!("Could not write an event: {}", e);
+        }
+    }
+}
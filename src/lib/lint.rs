@@ -0,0 +1,256 @@
+use std::fmt::{Display, Formatter};
+
+use crate::conf::{FakeCIRepoConfig, Image, ImageVersion};
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::lint::{lint, Lint, Severity};
+    use crate::utils::tests::{deser_yaml, get_sample_resource_file};
+
+    fn find<'a>(lints: &'a [Lint], job: Option<&str>, needle: &str) -> Option<&'a Lint> {
+        lints
+            .iter()
+            .find(|l| l.job.as_deref() == job && l.message.contains(needle))
+    }
+
+    #[test]
+    fn lints() {
+        let s = get_sample_resource_file("lints.yml").expect("could not find lints.yml");
+        let c = deser_yaml(&s).expect("could not deserialize lints.yml");
+        let lints = lint(&c);
+        let expected = [
+            (None, "privileged", Severity::Warning),
+            (None, "image docker has no tag", Severity::Warning),
+            (
+                Some("latest"),
+                "image rust:latest has no tag",
+                Severity::Warning,
+            ),
+            (Some("no steps"), "no steps", Severity::Warning),
+            (Some("empty exec"), "no commands", Severity::Info),
+            (Some("leaky secret"), "API_KEY", Severity::Warning),
+            (Some("docker in docker"), "docker socket", Severity::Warning),
+            (Some("on host"), "on the host", Severity::Warning),
+            (Some("on host"), "nothing in host jobs", Severity::Info),
+            (
+                Some("sidecars"),
+                "\"push\" gets the docker socket",
+                Severity::Warning,
+            ),
+            (
+                Some("sidecars"),
+                "\"mount\" runs privileged",
+                Severity::Warning,
+            ),
+        ];
+        for (job, needle, severity) in expected {
+            let l = find(&lints, job, needle)
+                .unwrap_or_else(|| panic!("no lint about {} for {:?}", needle, job));
+            assert_eq!(l.severity, severity);
+        }
+        assert_eq!(lints.len(), expected.len());
+    }
+
+    #[test]
+    fn clean_config() {
+        let c = deser_yaml(
+            "default:
+  image: ubuntu:22.04
+pipeline:
+  - name: build
+    steps:
+      - exec:
+          - make
+  - name: pinned
+    image: ubuntu@sha256:45b23dee08af5e43a7fea6c4cf9c25ccf269ee113168c19722f87876677c5cb2
+    steps:
+      - exec:
+          - make test",
+        )
+        .expect("could not deserialize config");
+        assert_eq!(lint(&c), vec![]);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+/// How bad a [Lint] is
+pub enum Severity {
+    /// Probably a leftover, but harmless
+    Info,
+    /// Likely a mistake, or a security hazard
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+/// A finding about a [configuration](FakeCIRepoConfig) that is valid, but smells
+pub struct Lint {
+    /// How bad it is
+    pub severity: Severity,
+    /// The [job](crate::conf::FakeCIJob) it is about, if any. None means the defaults.
+    pub job: Option<String>,
+    /// What's wrong
+    pub message: String,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.job {
+            Some(job) => write!(f, "{} [{}]: {}", self.severity, job, self.message),
+            None => write!(f, "{} [default]: {}", self.severity, self.message),
+        }
+    }
+}
+
+fn privileged_image(image: Option<&Image>) -> bool {
+    image.map(|i| i.is_privileged()).unwrap_or(false)
+}
+
+/// Returns the reference of a pulled image whose version moves over time
+fn floating_image(image: Option<&Image>) -> Option<&str> {
+    let reference = match image {
+        Some(Image::Existing(s)) if s != "local" => s,
+        Some(Image::ExistingFull(e)) => &e.name,
+        _ => return None,
+    };
+    match ImageVersion::of(reference).is_floating() {
+        true => Some(reference),
+        false => None,
+    }
+}
+
+fn floating_image_message(reference: &str) -> String {
+    format!(
+        "image {} has no tag, or uses latest; pin a tag or a digest for reproducible builds",
+        reference
+    )
+}
+
+/// Looks for common mistakes in `config`: jobs without steps, steps without commands, secrets
+/// shadowed by plaintext env, mounted docker sockets, host jobs, privileged containers & steps and
+/// images not pinned to a tag or digest.
+pub fn lint(config: &FakeCIRepoConfig) -> Vec<Lint> {
+    let mut lints = vec![];
+    let mut push = |severity, job: Option<&str>, message: String| {
+        lints.push(Lint {
+            severity,
+            job: job.map(String::from),
+            message,
+        })
+    };
+    if let Some(default) = &config.default {
+        if privileged_image(default.image.as_ref()) {
+            push(
+                Severity::Warning,
+                None,
+                "default image runs privileged; every job using it gets root on the host"
+                    .to_string(),
+            );
+        }
+        if let Some(reference) = floating_image(default.image.as_ref()) {
+            push(Severity::Warning, None, floating_image_message(reference));
+        }
+    }
+    for job in &config.pipeline {
+        let name = Some(job.name.as_str());
+        if job.steps.is_empty() {
+            push(Severity::Warning, name, "job has no steps".to_string());
+        }
+        for (i, step) in job.steps.iter().enumerate() {
+            let step_name = step
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("step {}", i + 1));
+            if step.exec.is_empty() {
+                push(
+                    Severity::Info,
+                    name,
+                    format!("step \"{}\" has no commands", step_name),
+                );
+            }
+            match (job.runs_on_host(), step.privileged, step.docker) {
+                (_, false, false) => {}
+                (true, _, _) => push(
+                    Severity::Info,
+                    name,
+                    format!(
+                        "step \"{}\" is privileged or gets the docker socket, which does nothing in host jobs",
+                        step_name
+                    ),
+                ),
+                (false, privileged, docker) => {
+                    if docker {
+                        push(
+                            Severity::Warning,
+                            name,
+                            format!(
+                                "step \"{}\" gets the docker socket, which gives root on the host",
+                                step_name
+                            ),
+                        );
+                    }
+                    if privileged {
+                        push(
+                            Severity::Warning,
+                            name,
+                            format!(
+                                "step \"{}\" runs privileged, which gives root on the host",
+                                step_name
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+        for secret in job.secrets.iter().filter(|s| job.env.contains_key(*s)) {
+            push(
+                Severity::Warning,
+                name,
+                format!(
+                    "{} is both a secret and a plaintext env var; the plaintext value is committed",
+                    secret
+                ),
+            );
+        }
+        for volume in job.volumes.iter().filter(|v| v.contains("docker.sock")) {
+            push(
+                Severity::Warning,
+                name,
+                format!(
+                    "volume {} mounts the docker socket, which gives root on the host",
+                    volume
+                ),
+            );
+        }
+        if job.runs_on_host() {
+            push(
+                Severity::Warning,
+                name,
+                "job runs on the host, without any isolation".to_string(),
+            );
+        }
+        for image in job.image.iter().chain(&job.images) {
+            if let Some(reference) = floating_image(Some(image)) {
+                push(Severity::Warning, name, floating_image_message(reference));
+            }
+            if privileged_image(Some(image)) {
+                push(
+                    Severity::Warning,
+                    name,
+                    "image runs privileged, which gives root on the host".to_string(),
+                );
+            }
+        }
+    }
+    lints
+}
@@ -1,28 +1,36 @@
+use std::collections::HashMap;
+
 use anyhow::anyhow;
 use chrono::{DateTime, Utc};
-use handlebars::{handlebars_helper, Handlebars};
+use handlebars::{handlebars_helper, no_escape, Handlebars};
 use lazy_static::lazy_static;
 use lettre::{ClientSecurity, SendableEmail, SmtpClient, SmtpTransport, Transport};
-use lettre_email::EmailBuilder;
-use log::{debug, trace};
+use lettre_email::{mime, Email, EmailBuilder};
+use log::{debug, trace, warn};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 
 use crate::notifications::Notify;
-use crate::{ExecutionResult, JobResult};
+use crate::utils::git::Commit;
+use crate::ExecutionResult;
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
     use chrono::{Duration, Utc};
+    use lettre::SendableEmail;
     use log::debug;
     use pretty_assertions::assert_eq;
     use pretty_env_logger::try_init;
     use serde_json::json;
 
-    use crate::notifications::mail::{render_text, Mailer};
+    use crate::notifications::mail::{log_file_name, participants, render_text, Mailer};
     use crate::notifications::Notify;
-    use crate::utils::git::CommitPerson;
+    use crate::utils::git::{parse_raw_commit, CommitPerson};
     use crate::utils::tests::get_sample_resource_file;
     use crate::{Commit, ExecutionContext, ExecutionResult, JobResult};
 
@@ -46,13 +54,15 @@ mod tests {
                     author: CommitPerson {
                         name: "coincoin".to_string(),
                         email: "example@example.fr".to_string(),
-                        date: Utc::now(),
+                        ..Default::default()
                     },
                     ..Default::default()
                 },
+                ..Default::default()
             },
             start_date: Utc::now() - Duration::seconds(100),
             end_date: Utc::now(),
+            ..Default::default()
         };
 
         let s = get_sample_resource_file("notifications/simple_smtp.yml")
@@ -74,6 +84,7 @@ mod tests {
                     start_date: Utc::now() - Duration::seconds(300),
                     end_date: Utc::now() - Duration::seconds(200),
                     logs: vec!["line 1".to_string(), "line 2".to_string()],
+                    ..Default::default()
                 },
                 JobResult {
                     success: true,
@@ -81,6 +92,7 @@ mod tests {
                     start_date: Utc::now() - Duration::seconds(190),
                     end_date: Utc::now(),
                     logs: vec!["line 3".to_string(), "line 4".to_string()],
+                    ..Default::default()
                 },
             ],
             context: ExecutionContext {
@@ -88,16 +100,290 @@ mod tests {
                 repo_url: "git@tests:fake-ci/internal-tests".to_string(),
                 branch: "main".to_string(),
                 commit: Default::default(),
+                ..Default::default()
             },
             start_date: Utc::now() - Duration::seconds(300),
             end_date: Utc::now(),
+            ..Default::default()
         };
         debug!("context: {:#?}", json!(exec_res));
-        let s = render_text(&exec_res);
+        let s = render_text(&exec_res, false, &HashMap::new());
         debug!("result: {:#?}", s);
         assert!(s.is_ok());
         let s = s.unwrap();
         debug!("rendered template: \n{:?}", s);
+        assert!(!s.0.contains("Skipped"));
+        assert!(s.0.contains(": Success"));
+
+        let skipped = ExecutionResult {
+            skipped: Some("commit message contains [skip ci]".to_string()),
+            ..Default::default()
+        };
+        let (txt, html) = render_text(&skipped, false, &HashMap::new()).expect("could not render");
+        assert!(txt.contains("Skipped: commit message contains [skip ci]"));
+        assert!(html.contains("Skipped: commit message contains [skip ci]"));
+        assert!(!txt.contains("Merge commit"));
+        assert!(!txt.contains("Commits built"));
+
+        let merge = ExecutionResult {
+            context: ExecutionContext {
+                commit: Commit {
+                    hash: "b4ff70f".to_string(),
+                    message: "Merge pull request #12 from paulollivier/repository-watching"
+                        .to_string(),
+                    parents: vec!["17af6fe".to_string(), "6aa86ed".to_string()],
+                    ..Default::default()
+                },
+                previous_commit: Some("17af6fe".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (txt, html) = render_text(&merge, false, &HashMap::new()).expect("could not render");
+        for s in [&txt, &html] {
+            assert!(s.contains("Merge commit: Merge pull request #12"));
+            assert!(s.contains("Commits built: 17af6fe..b4ff70f"));
+        }
+    }
+
+    #[test]
+    fn attach_logs() {
+        let logs: Vec<String> = (0..1000).map(|i| format!("log line {}", i)).collect();
+        let exec_res = ExecutionResult {
+            job_results: vec![JobResult {
+                success: false,
+                name: "unit tests".to_string(),
+                logs,
+                ..Default::default()
+            }],
+            context: ExecutionContext {
+                branch: "main".to_string(),
+                commit: Commit {
+                    author: CommitPerson {
+                        name: "coincoin".to_string(),
+                        email: "example@example.fr".to_string(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let s = get_sample_resource_file("notifs/simple_smtp.yml")
+            .expect("could not read simple_smtp.yml");
+        let mut mailer: Mailer = serde_yaml::from_str(&s).expect("could not build mailer");
+        assert!(!mailer.attach_logs);
+        let message = |mailer: &Mailer| {
+            let email: SendableEmail = mailer.build(&exec_res).expect("could not build").into();
+            email.message_to_string().expect("could not read the mail")
+        };
+        let inline = message(&mailer);
+        assert!(inline.contains("log line 999"));
+        assert!(!inline.contains("Content-Disposition: attachment"));
+
+        mailer.attach_logs = true;
+        let attached = message(&mailer);
+        assert!(attached.contains("Full logs attached"));
+        assert!(!attached.contains("log line 999"));
+        let (headers, body) = attached
+            .split_once("Content-Disposition: attachment")
+            .and_then(|(_, part)| part.split_once("\r\n\r\n"))
+            .expect("no attachment");
+        assert!(headers.starts_with("; filename=\"unit_tests.log.txt\""));
+        let body = body.split("\r\n--").next().unwrap().replace("\r\n", "");
+        let logs = STANDARD
+            .decode(body)
+            .expect("could not decode the attachment");
+        assert_eq!(
+            String::from_utf8(logs).unwrap(),
+            format!("{}\n", exec_res.job_results[0].logs.join("\n"))
+        );
+        assert_eq!(log_file_name("build/linux x86"), "build_linux_x86.log.txt");
+    }
+
+    #[test]
+    fn commit_participants() {
+        let commit = parse_raw_commit(
+            "commit 970683e1d18cf8229795fc8346ef6f66c0e8b2b0
+tree 0c7f2dba4403ebcfc576cb7fb0e9c7273b12eab9
+author Jane Doe <jane@example.org> 1638209781 +0100
+committer Merge Bot <bot@example.org> 1638209781 +0100
+
+    Add the notification interface
+
+    With the mailer as its first notifier.
+
+    Co-authored-by: John Smith <john@example.org>
+    Reviewed-by: Someone Else <someone@example.org>
+    Co-authored-by: Jane Doe <JANE@example.org>
+    co-authored-by: Alice <alice@example.org>
+    Co-authored-by: Merge Bot <bot@example.org>",
+        )
+        .expect("could not parse commit");
+        assert_eq!(commit.trailers.len(), 5);
+        assert_eq!(commit.trailers[1].key, "Reviewed-by");
+        assert_eq!(
+            commit.co_authors(),
+            vec![
+                "John Smith <john@example.org>",
+                "Jane Doe <JANE@example.org>",
+                "Alice <alice@example.org>",
+                "Merge Bot <bot@example.org>"
+            ]
+        );
+        // the author & duplicates only once
+        assert_eq!(
+            participants(&commit),
+            vec![
+                "Merge Bot <bot@example.org>",
+                "John Smith <john@example.org>",
+                "Alice <alice@example.org>"
+            ]
+        );
+
+        // a paragraph that isn't only trailers has none
+        let commit = parse_raw_commit(
+            "commit 970683e1d18cf8229795fc8346ef6f66c0e8b2b0
+author Jane Doe <jane@example.org> 1638209781 +0100
+committer Jane Doe <jane@example.org> 1638209781 +0100
+
+    Fix: the subject is not a trailer
+
+    Note: this isn't a trailer paragraph
+    since this line isn't one",
+        )
+        .expect("could not parse commit");
+        assert!(commit.trailers.is_empty());
+        assert!(participants(&commit).is_empty());
+    }
+
+    #[test]
+    fn invalid_participants() {
+        let _ = try_init();
+        let commit = parse_raw_commit(
+            "commit 970683e1d18cf8229795fc8346ef6f66c0e8b2b0
+author Jane Doe <jane@example.org> 1638209781 +0100
+committer Merge Bot <bot@example.org> 1638209781 +0100
+
+    Add the notification interface
+
+    Co-authored-by: John Smith <john.@example..org>
+    Co-authored-by: Alice <alice@example.org>",
+        )
+        .expect("could not parse commit");
+        let exec_res = ExecutionResult {
+            context: ExecutionContext {
+                branch: "main".to_string(),
+                commit,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let s = get_sample_resource_file("notifs/simple_smtp.yml")
+            .expect("could not read simple_smtp.yml");
+        let mut mailer: Mailer = serde_yaml::from_str(&s).expect("could not build mailer");
+        mailer.notify_participants = true;
+        let email: SendableEmail = mailer.build(&exec_res).expect("could not build").into();
+        let to: Vec<String> = email
+            .envelope()
+            .to()
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(
+            to,
+            vec!["jane@example.org", "bot@example.org", "alice@example.org"]
+        );
+    }
+
+    #[test]
+    fn subject_template() {
+        let exec_res = ExecutionResult {
+            job_results: vec![
+                JobResult {
+                    success: true,
+                    name: "build".to_string(),
+                    ..Default::default()
+                },
+                JobResult {
+                    success: false,
+                    name: "test".to_string(),
+                    ..Default::default()
+                },
+            ],
+            context: ExecutionContext {
+                repo_name: "fake-ci & co".to_string(),
+                branch: "main".to_string(),
+                commit: Commit {
+                    hash: "0123456789abcdef0123456789abcdef01234567".to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let s = get_sample_resource_file("notifs/simple_smtp.yml")
+            .expect("could not read simple_smtp.yml");
+        let mut mailer: Mailer = serde_yaml::from_str(&s).expect("could not build mailer");
+        assert_eq!(
+            mailer.subject(&exec_res).unwrap(),
+            "build results for main: Failure"
+        );
+
+        mailer.subject_template = Some(
+            "[{{context.repo_name}}#{{context.branch}}] {{short_hash}}: {{build_status}}, \
+             {{jobs_passed}}/{{jobs_total}} jobs passed"
+                .to_string(),
+        );
+        assert_eq!(
+            mailer.subject(&exec_res).unwrap(),
+            "[fake-ci & co#main] 0123456: Failure, 1/2 jobs passed"
+        );
+
+        // a failure the notifications ignore is a success, though the job still failed
+        let mut ignored = exec_res;
+        ignored.job_results[1].notify_ignore = true;
+        assert_eq!(
+            mailer.subject(&ignored).unwrap(),
+            "[fake-ci & co#main] 0123456: Success, 1/2 jobs passed"
+        );
+
+        mailer.subject_template = Some("{{#if}}".to_string());
+        assert!(mailer.subject(&ignored).is_err());
+    }
+
+    #[test]
+    fn extra_context() {
+        let exec_res = ExecutionResult {
+            job_results: vec![JobResult {
+                success: true,
+                name: "deploy".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let s = get_sample_resource_file("notifs/simple_smtp.yml")
+            .expect("could not read simple_smtp.yml");
+        let mut mailer: Mailer = serde_yaml::from_str(&s).expect("could not build mailer");
+        mailer.extra_context = HashMap::from([
+            (
+                "deploy_url".to_string(),
+                "https://staging.example.org".to_string(),
+            ),
+            ("build_status".to_string(), "overridden".to_string()),
+        ]);
+        mailer.subject_template =
+            Some("{{build_status}}: {{extra.deploy_url}} ({{extra.build_status}})".to_string());
+        assert_eq!(
+            mailer.subject(&exec_res).unwrap(),
+            "Success: https://staging.example.org (overridden)"
+        );
+        let (txt, html) =
+            render_text(&exec_res, false, &mailer.extra_context).expect("could not render");
+        assert!(txt.contains("deploy_url: https://staging.example.org"));
+        assert!(txt.contains(": Success"));
+        assert!(html.contains("<li>deploy_url: https://staging.example.org</li>"));
     }
 }
 lazy_static! {
@@ -106,20 +392,15 @@ lazy_static! {
 }
 
 // TODO: handle auth (ssl brrr)
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Default)]
 #[serde(untagged)]
 /// enum of supported SMTP auth methods
 pub enum SMTPAuth {
     /// No auth; the server accepts everything & anyone
+    #[default]
     None,
 }
 
-impl Default for SMTPAuth {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 fn is_default<T: Default + PartialEq>(t: &T) -> bool {
     t == &T::default()
 }
@@ -149,33 +430,133 @@ pub struct Mailer {
     pub(crate) recipients: Option<Vec<String>>,
     /// Which SMTP config should we use
     pub(crate) server: SMTPConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Handlebars template of the mail's subject, rendered with the same data as its body.
+    /// Ex: `[{{context.repo_name}}] {{short_hash}}: {{jobs_passed}}/{{jobs_total}} jobs passed`
+    pub(crate) subject_template: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Also send the mail to the commit's other [participants](participants): its co-authors,
+    /// and its committer
+    pub(crate) notify_participants: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    /// Attach the full logs of each job as a `.log.txt` file, instead of inlining them in the
+    /// body, which then only summarizes the results
+    pub(crate) attach_logs: bool,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    /// Values computed elsewhere, such as a deploy URL, added to the data the templates are
+    /// rendered with under `extra`: `{{extra.deploy_url}}`. The mail's body lists them
+    pub(crate) extra_context: HashMap<String, String>,
 }
 
-fn render_text(ctx: &ExecutionResult) -> anyhow::Result<(String, String)> {
-    let mut reg = Handlebars::new();
-    handlebars_helper!(status: |job_results: Vec<JobResult>| {
-        match job_results.iter().any(|r| !r.success) {
-            true => "Failure",
-            false => "Success",
+/// The people who took part in `commit`, besides its author: its committer, and its co-authors
+/// (from its `Co-authored-by` trailers), as `Name <email>`. Each one appears once.
+fn participants(commit: &Commit) -> Vec<String> {
+    let mut seen = vec![commit.author.email.to_lowercase()];
+    let mut participants = vec![];
+    let committer = format!("{} <{}>", commit.committer.name, commit.committer.email);
+    for p in std::iter::once(committer.as_str()).chain(commit.co_authors()) {
+        let email = match EMAIL_REGEX.captures(p) {
+            Some(c) => c[2].to_lowercase(),
+            None => continue,
+        };
+        if !seen.contains(&email) {
+            seen.push(email);
+            participants.push(p.to_string());
         }
-    });
+    }
+    participants
+}
+
+/// The registry rendering the mails, with its helpers
+fn registry() -> Handlebars<'static> {
+    let mut reg = Handlebars::new();
     handlebars_helper!(duration: |start: DateTime<Utc>, end: DateTime<Utc>| {
         format!("{}", (end - start).num_seconds())
     });
-    reg.register_helper("build_status", Box::new(status));
     reg.register_helper("duration", Box::new(duration));
+    reg
+}
+
+/// The data the templates are rendered with: the [ExecutionResult], plus a few computed values,
+/// and the `extra` ones, kept apart so that they can't replace any other
+fn template_data(ctx: &ExecutionResult, extra: &HashMap<String, String>) -> Value {
+    let mut data = json!(ctx);
+    data["extra"] = json!(extra);
+    data["logs_attached"] = json!(false);
+    data["build_status"] = json!(match ctx.notified_success() {
+        true => "Success",
+        false => "Failure",
+    });
+    data["short_hash"] = json!(ctx.context.commit.hash.chars().take(7).collect::<String>());
+    data["is_merge"] = json!(ctx.context.commit.is_merge());
+    data["jobs_total"] = json!(ctx.job_results.len());
+    data["jobs_passed"] = json!(ctx.job_results.iter().filter(|j| j.success).count());
+    data
+}
+
+/// The text & HTML bodies of the mail. Without the logs when they are `logs_attached`
+fn render_text(
+    ctx: &ExecutionResult,
+    logs_attached: bool,
+    extra: &HashMap<String, String>,
+) -> anyhow::Result<(String, String)> {
+    let reg = registry();
+    let mut data = template_data(ctx, extra);
+    if logs_attached {
+        data["logs_attached"] = json!(true);
+        for job in data["job_results"].as_array_mut().into_iter().flatten() {
+            job["logs"] = json!([]);
+        }
+    }
     Ok((
         reg.render_template(
             include_str!("../../../resources/templates/notifs/mail.txt.hbs"),
-            &json!(ctx),
+            &data,
         )?,
         reg.render_template(
             include_str!("../../../resources/templates/notifs/mail.html.hbs"),
-            &json!(ctx),
+            &data,
         )?,
     ))
 }
 
+/// The name of the file holding the logs of the job `name`, safe for any mail client
+fn log_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+    format!("{}.log.txt", name)
+}
+
+impl Mailer {
+    /// The mail's subject, from the [subject_template](Mailer::subject_template) if any
+    fn subject(&self, exec_res: &ExecutionResult) -> anyhow::Result<String> {
+        match &self.subject_template {
+            Some(template) => {
+                let mut reg = registry();
+                // a subject is plain text
+                reg.register_escape_fn(no_escape);
+                Ok(reg.render_template(template, &template_data(exec_res, &self.extra_context))?)
+            }
+            None => Ok(format!(
+                "build results for {}: {}",
+                exec_res.context.branch,
+                match (&exec_res.skipped, exec_res.notified_success()) {
+                    (Some(_), _) => "Skipped",
+                    (None, true) => "Success!",
+                    (None, false) => "Failure",
+                }
+            )),
+        }
+    }
+}
+
 enum EmailAddress {
     Single(String),
     Complete(String, String),
@@ -201,10 +582,33 @@ fn to_addr(s: &str) -> anyhow::Result<EmailAddress> {
     ))
 }
 
-impl Notify for Mailer {
-    fn send(&self, exec_res: &ExecutionResult) -> anyhow::Result<()> {
+impl Mailer {
+    /// The mail telling about `exec_res`
+    fn build(&self, exec_res: &ExecutionResult) -> anyhow::Result<Email> {
         let to = exec_res.context.commit.author.to_addr();
-        let email = EmailBuilder::new().to(to);
+        let mut email = EmailBuilder::new().to(to);
+        if self.notify_participants {
+            for p in participants(&exec_res.context.commit) {
+                // the commit is anyone's to write: a bad co-author shouldn't cost everyone the mail
+                let addr = match to_addr(&p) {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        warn!("Not mailing participant {}: {}", p, e);
+                        continue;
+                    }
+                };
+                let (EmailAddress::Single(e) | EmailAddress::Complete(e, _)) = &addr;
+                if let Err(e) = lettre::EmailAddress::new(e.clone()) {
+                    warn!("Not mailing participant {}: {}", p, e);
+                    continue;
+                }
+                debug!("Adding participant {} to recipients", p);
+                email = match addr {
+                    EmailAddress::Single(s) => email.to(s),
+                    EmailAddress::Complete(e, n) => email.to((e, n)),
+                }
+            }
+        }
         let mut email = match to_addr(&self.from)? {
             EmailAddress::Single(s) => {
                 trace!("mail from {}", s);
@@ -224,20 +628,30 @@ impl Notify for Mailer {
                 }
             }
         }
-        let (txt, html) = render_text(exec_res)?;
-        let email = email
-            .subject(format!(
-                "build results for {}: {}",
-                exec_res.context.branch,
-                match exec_res.job_results.iter().any(|r| !r.success) {
-                    false => "Success!",
-                    true => "Failure",
-                }
-            ))
-            .text(txt)
-            .html(html)
+        let (txt, html) = render_text(exec_res, self.attach_logs, &self.extra_context)?;
+        let mut email = email.subject(self.subject(exec_res)?).text(txt).html(html);
+        if self.attach_logs {
+            for job in &exec_res.job_results {
+                let mut logs = job.logs.join("\n");
+                logs.push('\n');
+                email = email
+                    .attachment(
+                        logs.as_bytes(),
+                        &log_file_name(&job.name),
+                        &mime::TEXT_PLAIN_UTF_8,
+                    )
+                    .map_err(|e| anyhow!("Could not attach the logs of {}: {}", job.name, e))?;
+            }
+        }
+        email
             .build()
-            .expect("Error while building mail!");
+            .map_err(|e| anyhow!("Could not build the mail: {}", e))
+    }
+}
+
+impl Notify for Mailer {
+    fn send(&self, exec_res: &ExecutionResult) -> anyhow::Result<()> {
+        let email = self.build(exec_res)?;
         let mut mailer = SmtpTransport::new(SmtpClient::new(
             format!("{}:{}", self.server.addr, self.server.port),
             ClientSecurity::None,
@@ -1,13 +1,284 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
 use anyhow::Result;
+use glob::Pattern;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 
+use crate::error::FakeCiError;
+#[cfg(feature = "github")]
+use crate::notifications::github::GithubStatus;
 #[cfg(feature = "mails")]
 use crate::notifications::mail::Mailer;
-use crate::ExecutionResult;
+#[cfg(feature = "webhook")]
+use crate::notifications::webhook::Webhook;
+use crate::{ExecutionContext, ExecutionResult, JobResult};
 
+#[cfg(feature = "github")]
+/// Commit statuses on GitHub
+pub mod github;
 #[cfg(feature = "mails")]
 /// Mail notifications
 pub mod mail;
+#[cfg(feature = "webhook")]
+/// JSON summaries posted to a URL, as chat apps' incoming webhooks expect
+pub mod webhook;
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use anyhow::anyhow;
+    use chrono::{Duration, Utc};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[cfg(feature = "mails")]
+    use crate::notifications::NotifierConfig;
+    use crate::notifications::{
+        send_with_retry, summarize, JobStatus, JobSummary, NotifierRegistry, Notify, Retry,
+    };
+    #[cfg(feature = "mails")]
+    use crate::utils::git::CommitPerson;
+    #[cfg(feature = "mails")]
+    use crate::Commit;
+    use crate::{ExecutionContext, ExecutionResult, JobResult};
+
+    /// Fails its first `self.0` sends
+    struct Flaky(u32, Cell<u32>);
+
+    impl Notify for Flaky {
+        fn send(&self, _exec_res: &ExecutionResult) -> anyhow::Result<()> {
+            self.1.set(self.1.get() + 1);
+            match self.1.get() > self.0 {
+                true => Ok(()),
+                false => Err(anyhow!("attempt {} failed", self.1.get())),
+            }
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let start = Utc::now();
+        let job = |name: &str, success, secs, logs: &[&str]| JobResult {
+            name: name.to_string(),
+            success,
+            start_date: start,
+            end_date: start + Duration::seconds(secs),
+            logs: logs.iter().map(|l| l.to_string()).collect(),
+            ..Default::default()
+        };
+        let res = ExecutionResult {
+            job_results: vec![
+                job("build", true, 90, &["compiling", "done"]),
+                job(
+                    "test",
+                    false,
+                    12,
+                    &["running 3 tests", "ok\nok\nFAILED", "1 failed"],
+                ),
+                JobResult {
+                    allow_failure: true,
+                    ..job("lint", false, 1, &["warning"])
+                },
+            ],
+            ..Default::default()
+        };
+        let summary = summarize(&res, 3);
+        assert!(!summary.success);
+        assert_eq!(summary.text, "2 of 3 jobs failed");
+        assert_eq!(
+            summary.jobs,
+            vec![
+                JobSummary {
+                    name: "build".to_string(),
+                    status: JobStatus::Success,
+                    duration_ms: 90_000,
+                    log_tail: vec![],
+                },
+                JobSummary {
+                    name: "test".to_string(),
+                    status: JobStatus::Failure,
+                    duration_ms: 12_000,
+                    log_tail: vec![
+                        "ok".to_string(),
+                        "FAILED".to_string(),
+                        "1 failed".to_string()
+                    ],
+                },
+                JobSummary {
+                    name: "lint".to_string(),
+                    status: JobStatus::AllowedFailure,
+                    duration_ms: 1_000,
+                    log_tail: vec!["warning".to_string()],
+                },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&summary.jobs[0]).unwrap(),
+            json!({"name": "build", "status": "success", "duration_ms": 90000})
+        );
+        assert!(summarize(&res, 0)
+            .jobs
+            .iter()
+            .all(|j| j.log_tail.is_empty()));
+    }
+
+    #[test]
+    fn notify_ignore() {
+        let res = ExecutionResult {
+            job_results: vec![
+                JobResult {
+                    name: "build".to_string(),
+                    success: true,
+                    ..Default::default()
+                },
+                JobResult {
+                    name: "nightly bench".to_string(),
+                    success: false,
+                    notify_ignore: true,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        // the pipeline failed, but the notifications say it didn't
+        assert!(!res.overall_success());
+        assert!(res.notified_success());
+        let summary = summarize(&res, 0);
+        assert!(summary.success);
+        // the ignored job is still listed as failed
+        assert_eq!(summary.jobs[1].name, "nightly bench");
+        assert_eq!(summary.jobs[1].status, JobStatus::Failure);
+    }
+
+    /// Remembers the branches it was told about
+    #[derive(Default)]
+    struct Recording(Rc<RefCell<Vec<String>>>);
+
+    impl Notify for Recording {
+        fn send(&self, exec_res: &ExecutionResult) -> anyhow::Result<()> {
+            self.0
+                .borrow_mut()
+                .push(exec_res.context.branch.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registry() {
+        let no_retry = Retry {
+            attempts: 0,
+            backoff_ms: 0,
+        };
+        let recording = Recording::default();
+        let received = Rc::clone(&recording.0);
+        let mut registry = NotifierRegistry::new(&[]);
+        registry
+            .register(Box::new(Flaky(u32::MAX, Cell::new(0))), no_retry.clone())
+            .register(Box::new(recording), no_retry);
+        assert_eq!(registry.notifiers("dev").len(), 2);
+
+        let res = ExecutionResult {
+            context: ExecutionContext {
+                branch: "dev".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let errors = registry.send(&res);
+        // the broken notifier didn't stop the other one
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "attempt 1 failed");
+        assert_eq!(*received.borrow(), vec!["dev"]);
+
+        // offline, nothing is sent, and nothing can fail
+        registry.offline(true);
+        assert!(registry.notifiers("dev").is_empty());
+        assert!(registry.send(&res).is_empty());
+        assert_eq!(*received.borrow(), vec!["dev"]);
+    }
+
+    #[test]
+    fn retry() {
+        let retry = Retry {
+            attempts: 2,
+            backoff_ms: 0,
+        };
+        let flaky = Flaky(2, Cell::new(0));
+        assert!(send_with_retry(&flaky, &ExecutionResult::default(), &retry).is_ok());
+        assert_eq!(flaky.1.get(), 3);
+
+        let broken = Flaky(u32::MAX, Cell::new(0));
+        let err = send_with_retry(&broken, &ExecutionResult::default(), &retry)
+            .expect_err("should have given up");
+        assert_eq!(err.to_string(), "attempt 3 failed");
+        assert_eq!(broken.1.get(), 3);
+
+        let serde_default: Retry = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(serde_default, Retry::default());
+    }
+
+    #[cfg(feature = "mails")]
+    fn result_on(branch: &str) -> ExecutionResult {
+        ExecutionResult {
+            context: ExecutionContext {
+                branch: branch.to_string(),
+                commit: Commit {
+                    author: CommitPerson {
+                        email: "dev@example.org".to_string(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "mails")]
+    fn branch_filter() {
+        // nothing listens on port 1: actually sending fails
+        let n: NotifierConfig = serde_yaml::from_str(
+            "type: mailer
+config:
+  from: fakeci@example.org
+  server:
+    addr: localhost
+    port: 1
+branches:
+  - main
+  - release/*",
+        )
+        .expect("could not deserialize notifier");
+        assert!(n.should_notify("main"));
+        assert!(n.should_notify("release/1.0"));
+        assert!(!n.should_notify("dev"));
+        assert!(n.send(&result_on("dev")).is_ok());
+        assert!(n.send(&result_on("main")).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "mails")]
+    fn no_branch_filter() {
+        let n: NotifierConfig = serde_yaml::from_str(
+            "type: mailer
+config:
+  from: fakeci@example.org
+  server:
+    addr: localhost
+    port: 1",
+        )
+        .expect("could not deserialize notifier");
+        assert!(n.branches.is_empty());
+        assert!(n.should_notify("dev"));
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(tag = "type", content = "config", rename_all = "snake_case")]
@@ -16,19 +287,373 @@ pub enum Notifier {
     #[cfg(feature = "mails")]
     /// Sending mails with SMTP
     Mailer(Mailer),
+    #[cfg(feature = "github")]
+    /// Reporting commit statuses to GitHub
+    GithubStatus(GithubStatus),
+    #[cfg(feature = "webhook")]
+    /// Posting a [Summary] to a URL
+    Webhook(Webhook),
 }
 
 impl Notifier {
-    /// Sends the communication, whatever the variant of Notifier it is
-    pub fn send(&self, exec_res: &ExecutionResult) -> Result<()> {
+    /// Its `type`, as in the config. Ex: `mailer`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "mails")]
+            Notifier::Mailer(_) => "mailer",
+            #[cfg(feature = "github")]
+            Notifier::GithubStatus(_) => "github_status",
+            #[cfg(feature = "webhook")]
+            Notifier::Webhook(_) => "webhook",
+        }
+    }
+
+    fn inner(&self) -> &dyn Notify {
         match self {
-            Notifier::Mailer(e) => e.send(exec_res),
+            #[cfg(feature = "mails")]
+            Notifier::Mailer(m) => m,
+            #[cfg(feature = "github")]
+            Notifier::GithubStatus(g) => g,
+            #[cfg(feature = "webhook")]
+            Notifier::Webhook(w) => w,
         }
     }
 }
 
+/// Notifies with whatever the variant of Notifier it is
+impl Notify for Notifier {
+    fn on_start(&self, ctx: &ExecutionContext) -> Result<()> {
+        self.inner().on_start(ctx)
+    }
+
+    fn send(&self, exec_res: &ExecutionResult) -> Result<()> {
+        self.inner().send(exec_res)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// A [Notifier], and the branches it cares about. Serializes to:
+/// ```yaml
+/// type: mailer
+/// config:
+///   from: fakeci@example.org
+/// branches: # optional, glob patterns. If absent, all branches are notified
+///   - main
+///   - release/*
+/// retry: # optional, see Retry
+///   attempts: 2
+/// ```
+pub struct NotifierConfig {
+    #[serde(flatten)]
+    /// The actual notifier
+    pub notifier: Notifier,
+    #[serde(default)]
+    /// Only the results of branches matching one of these glob patterns are sent.
+    /// Empty means all branches.
+    pub branches: Vec<String>,
+    #[serde(default)]
+    /// How sending is [retried](send_with_retry) when it fails
+    pub retry: Retry,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default)]
+/// How many times, and how patiently, a failed notification is sent again. Serializes to:
+/// ```yaml
+/// attempts: 2 # after the first one. 0 never retries
+/// backoff_ms: 1000 # wait before the first retry, doubled before each following one
+/// ```
+pub struct Retry {
+    /// How many times sending is retried after the first attempt failed
+    pub attempts: u32,
+    /// How long to wait before the first retry, in milliseconds. Doubled before each
+    /// following retry
+    pub backoff_ms: u64,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 2,
+            backoff_ms: 1000,
+        }
+    }
+}
+
+/// Sends `exec_res` with `notifier`, retrying failures such as a mail server or a network
+/// briefly unavailable, as `retry` says. Returns the error of the last attempt if none succeeded
+pub fn send_with_retry(
+    notifier: &dyn Notify,
+    exec_res: &ExecutionResult,
+    retry: &Retry,
+) -> Result<()> {
+    let mut backoff = Duration::from_millis(retry.backoff_ms);
+    let mut attempt = 0;
+    loop {
+        match notifier.send(exec_res) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= retry.attempts => return Err(e),
+            Err(e) => {
+                attempt += 1;
+                warn!(
+                    "Could not notify ({:#}), retrying in {:?} ({}/{})",
+                    e, backoff, attempt, retry.attempts
+                );
+                thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+            }
+        }
+    }
+}
+
+impl NotifierConfig {
+    /// Is `branch` one the notifier cares about?
+    pub fn should_notify(&self, branch: &str) -> bool {
+        self.branches.is_empty()
+            || self.branches.iter().any(|b| match Pattern::new(b) {
+                Ok(p) => p.matches(branch),
+                Err(e) => {
+                    warn!("Invalid notifier branch pattern {}: {}", b, e);
+                    false
+                }
+            })
+    }
+
+    /// Sends the communication, if the result's branch is [one we care about](Self::should_notify)
+    pub fn send(&self, exec_res: &ExecutionResult) -> Result<(), FakeCiError> {
+        if !self.should_notify(&exec_res.context.branch) {
+            debug!(
+                "Not notifying for branch {}, not in {:?}",
+                exec_res.context.branch, self.branches
+            );
+            return Ok(());
+        }
+        self.notifier
+            .send(exec_res)
+            .map_err(|e| FakeCiError::classify(e, FakeCiError::Notification))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+/// What the notifiers other than mails tell of an execution, so that they all tell the same.
+/// Built by [summarize]
+pub struct Summary {
+    /// The repository's name
+    pub repo_name: String,
+    /// The branch (or ref) built
+    pub branch: String,
+    /// The hash of the commit built
+    pub commit: String,
+    /// If it is a [merge commit](crate::utils::git::Commit::is_merge)
+    pub is_merge: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// What the branch pointed to before, if known: the commits after it were built
+    pub previous_commit: Option<String>,
+    /// [Overall](ExecutionResult::notified_success) success, as notified
+    pub success: bool,
+    /// One line telling how it went. Ex: `1 of 3 jobs failed`
+    pub text: String,
+    /// The jobs, in the order they ran
+    pub jobs: Vec<JobSummary>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    /// Values computed elsewhere, given by the notifier's config. Ex: a deploy URL
+    pub extra: HashMap<String, String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+/// A job of a [Summary]
+pub struct JobSummary {
+    /// The job's name
+    pub name: String,
+    /// How it ended
+    pub status: JobStatus,
+    /// How long it ran, in milliseconds
+    pub duration_ms: i64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    /// The last lines of its logs, if it failed
+    pub log_tail: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+/// How a job ended
+pub enum JobStatus {
+    /// All its steps passed
+    Success,
+    /// It failed, failing the pipeline
+    Failure,
+    /// It failed, but was [allowed to](JobResult::allow_failure)
+    AllowedFailure,
+    /// It didn't run, the pipeline being [cancelled](JobResult::skipped) before it
+    Skipped,
+}
+
+impl JobStatus {
+    fn of(job: &JobResult) -> Self {
+        if job.skipped {
+            return JobStatus::Skipped;
+        }
+        match (job.success, job.allow_failure) {
+            (true, _) => JobStatus::Success,
+            (false, false) => JobStatus::Failure,
+            (false, true) => JobStatus::AllowedFailure,
+        }
+    }
+}
+
+/// Describes the final state of an execution, in one line
+fn describe(exec_res: &ExecutionResult) -> String {
+    let failed = exec_res.job_results.iter().filter(|j| !j.success).count();
+    match &exec_res.skipped {
+        Some(reason) => format!("Skipped: {}", reason),
+        None if failed == 0 => format!("{} job(s) succeeded", exec_res.job_results.len()),
+        None if exec_res.notified_success() => format!(
+            "{} of {} jobs failed, within the allowed failures",
+            failed,
+            exec_res.job_results.len()
+        ),
+        None => format!("{} of {} jobs failed", failed, exec_res.job_results.len()),
+    }
+}
+
+/// Summarizes `exec_res`: how each job went, and the last `max_log_lines` lines of the logs of
+/// the failed ones, enough to know what broke without looking any further
+pub fn summarize(exec_res: &ExecutionResult, max_log_lines: usize) -> Summary {
+    let jobs = exec_res
+        .job_results
+        .iter()
+        .map(|j| {
+            let mut log_tail = vec![];
+            if !j.success {
+                let lines: Vec<&str> = j.logs.iter().flat_map(|l| l.lines()).collect();
+                let start = lines.len().saturating_sub(max_log_lines);
+                log_tail = lines[start..].iter().map(|l| l.to_string()).collect();
+            }
+            JobSummary {
+                name: j.name.to_string(),
+                status: JobStatus::of(j),
+                duration_ms: j.duration().num_milliseconds(),
+                log_tail,
+            }
+        })
+        .collect();
+    Summary {
+        repo_name: exec_res.context.repo_name.to_string(),
+        branch: exec_res.context.branch.to_string(),
+        commit: exec_res.context.commit.hash.to_string(),
+        is_merge: exec_res.context.commit.is_merge(),
+        previous_commit: exec_res.context.previous_commit.clone(),
+        success: exec_res.notified_success(),
+        text: describe(exec_res),
+        jobs,
+        extra: HashMap::new(),
+    }
+}
+
+/// The notifiers of a repository: those of its config, and custom ones, [registered](Self::register)
+/// by the program embedding fake-ci. Both are told the same, in the same way.
+/// ```rust
+/// # use fakeci::notifications::{NotifierRegistry, Notify, Retry};
+/// # use fakeci::ExecutionResult;
+/// struct Logger;
+///
+/// impl Notify for Logger {
+///     fn send(&self, exec_res: &ExecutionResult) -> anyhow::Result<()> {
+///         println!("{}: {}", exec_res.context.repo_name, exec_res.overall_success());
+///         Ok(())
+///     }
+/// }
+///
+/// let mut registry = NotifierRegistry::new(&[]);
+/// registry.register(Box::new(Logger), Retry::default());
+/// assert_eq!(registry.notifiers("main").len(), 1);
+/// ```
+pub struct NotifierRegistry<'a> {
+    configured: &'a [NotifierConfig],
+    custom: Vec<(Box<dyn Notify + 'a>, Retry)>,
+    offline: bool,
+}
+
+impl<'a> NotifierRegistry<'a> {
+    /// A registry of the `configured` notifiers only
+    pub fn new(configured: &'a [NotifierConfig]) -> Self {
+        NotifierRegistry {
+            configured,
+            custom: vec![],
+            offline: false,
+        }
+    }
+
+    /// Offline, nothing is sent: what would have been is logged instead
+    pub fn offline(&mut self, offline: bool) -> &mut Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Adds a custom notifier, told about all the branches. Its failures are retried as `retry`
+    /// says
+    pub fn register(&mut self, notifier: Box<dyn Notify + 'a>, retry: Retry) -> &mut Self {
+        self.custom.push((notifier, retry));
+        self
+    }
+
+    /// The notifiers caring about `branch`, and how to retry them
+    fn for_branch(&self, branch: &str) -> Vec<(&dyn Notify, &Retry)> {
+        self.configured
+            .iter()
+            .filter(|n| n.should_notify(branch))
+            .map(|n| (&n.notifier as &dyn Notify, &n.retry))
+            .chain(
+                self.custom
+                    .iter()
+                    .map(|(n, r)| (n.as_ref() as &dyn Notify, r)),
+            )
+            .collect()
+    }
+
+    /// The notifiers caring about `branch`, for [launch_with_notifiers](crate::launch_with_notifiers)
+    /// to tell when its pipeline starts. None, [offline](Self::offline)
+    pub fn notifiers(&self, branch: &str) -> Vec<&dyn Notify> {
+        if self.offline {
+            return vec![];
+        }
+        self.for_branch(branch)
+            .into_iter()
+            .map(|(n, _)| n)
+            .collect()
+    }
+
+    /// [Sends](send_with_retry) `exec_res` with all the notifiers caring about its branch. One
+    /// failing doesn't stop the others: the errors are returned once all were tried
+    pub fn send(&self, exec_res: &ExecutionResult) -> Vec<anyhow::Error> {
+        let notifiers = self.for_branch(&exec_res.context.branch);
+        if self.offline {
+            if !notifiers.is_empty() {
+                info!(
+                    "Offline, not telling {} notifier(s) about {}#{}: {}",
+                    notifiers.len(),
+                    exec_res.context.repo_name,
+                    exec_res.context.branch,
+                    describe(exec_res)
+                );
+            }
+            return vec![];
+        }
+        notifiers
+            .into_iter()
+            .filter_map(|(n, retry)| send_with_retry(n, exec_res, retry).err())
+            .collect()
+    }
+}
+
 /// Defines a [Notifier], who can communicate build results to the outside world
 pub trait Notify {
+    /// Called once the commit to build is known, right before the jobs run.
+    /// Does nothing by default
+    fn on_start(&self, _ctx: &ExecutionContext) -> Result<()> {
+        Ok(())
+    }
+
     /// validates the intention to communicate the result to the outside world
     fn send(&self, exec_res: &ExecutionResult) -> Result<()>;
 }
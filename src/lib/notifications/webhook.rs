@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::notifications::{summarize, Notify};
+use crate::ExecutionResult;
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::notifications::{Notifier, NotifierConfig, Notify};
+    use crate::ExecutionResult;
+
+    use super::Webhook;
+
+    #[test]
+    fn config() {
+        let n: NotifierConfig = serde_yaml::from_str(
+            "type: webhook
+config:
+  url: https://hooks.slack.com/services/T0/B0/xxx",
+        )
+        .expect("could not deserialize notifier");
+        match n.notifier {
+            Notifier::Webhook(w) => {
+                assert_eq!(w.url, "https://hooks.slack.com/services/T0/B0/xxx");
+                assert_eq!(w.max_log_lines, 20);
+                assert!(w.extra_context.is_empty());
+            }
+            #[allow(unreachable_patterns)]
+            n => panic!("not a webhook: {:?}", n),
+        }
+    }
+
+    #[test]
+    fn errors_hide_the_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not listen");
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut stream = listener
+                .incoming()
+                .next()
+                .unwrap()
+                .expect("could not accept");
+            let _ = stream.read(&mut [0; 4096]);
+            write!(
+                stream,
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )
+            .expect("could not answer");
+        });
+        let webhook = |url: String| Webhook {
+            url,
+            max_log_lines: 20,
+            extra_context: Default::default(),
+        };
+        let res = ExecutionResult::default();
+        let err = webhook(format!("http://{}/services/s3cr3t", addr))
+            .send(&res)
+            .expect_err("the hook is missing");
+        server.join().unwrap();
+        assert_eq!(err.to_string(), "webhook post failed: HTTP status 404");
+        // nothing listens anymore
+        let err = webhook(format!("http://{}/services/s3cr3t", addr))
+            .send(&res)
+            .expect_err("nothing listens");
+        assert!(!err.to_string().contains("s3cr3t"), "{}", err);
+    }
+}
+
+fn max_log_lines_default() -> usize {
+    20
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// Posts a [Summary](crate::notifications::Summary) of the results to a URL, as JSON. Its
+/// `text` makes it a valid message for Slack or Mattermost incoming webhooks. Serializes to:
+/// ```yaml
+/// url: https://hooks.slack.com/services/… # where to post
+/// max_log_lines: 20 # optional, how much of the logs of the failed jobs to send
+/// extra_context: # optional, sent as is, as the summary's `extra`
+///   deploy_url: https://staging.example.org
+/// ```
+pub struct Webhook {
+    /// Where the summary is posted. It often holds a secret, so it is never logged
+    pub(crate) url: String,
+    #[serde(default = "max_log_lines_default")]
+    /// How many of the last lines of the logs of each failed job are sent. Default: 20
+    pub(crate) max_log_lines: usize,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    /// Values computed elsewhere, such as a deploy URL, sent in the summary's `extra`
+    pub(crate) extra_context: HashMap<String, String>,
+}
+
+impl Notify for Webhook {
+    fn send(&self, exec_res: &ExecutionResult) -> Result<()> {
+        debug!("Posting the summary of the execution to a webhook");
+        let mut summary = summarize(exec_res, self.max_log_lines);
+        summary.extra = self.extra_context.clone();
+        // ureq's errors hold the url, which is not to be logged
+        match ureq::post(&self.url).send_json(summary) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, _)) => {
+                Err(anyhow!("webhook post failed: HTTP status {}", code))
+            }
+            Err(e) => Err(anyhow!("webhook post failed: {}", e.kind())),
+        }
+    }
+}
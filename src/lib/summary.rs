@@ -0,0 +1,177 @@
+use std::io::{stderr, stdout};
+
+use chrono::Duration;
+
+use crate::utils::color::{paint, use_color, Color};
+use crate::ExecutionResult;
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use pretty_assertions::assert_eq;
+
+    use crate::summary::{format_duration, render_summary};
+    use crate::{ExecutionResult, JobResult};
+
+    fn result() -> ExecutionResult {
+        let start = Utc.with_ymd_and_hms(2021, 11, 28, 12, 0, 0).unwrap();
+        ExecutionResult {
+            job_results: vec![
+                JobResult {
+                    success: true,
+                    name: "check".to_string(),
+                    start_date: start,
+                    end_date: start + Duration::seconds(4),
+                    logs: vec![],
+                    ..Default::default()
+                },
+                JobResult {
+                    success: false,
+                    name: "test & release".to_string(),
+                    start_date: start + Duration::seconds(4),
+                    end_date: start + Duration::seconds(79),
+                    logs: vec![],
+                    ..Default::default()
+                },
+            ],
+            start_date: start,
+            end_date: start + Duration::seconds(80),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summary() {
+        assert_eq!(
+            render_summary(&result(), false),
+            vec![
+                "JOB             STATUS  DURATION",
+                "check           ok      4s",
+                "test & release  FAILED  1m15s",
+                "1 of 2 jobs failed in 1m20s",
+            ]
+        );
+    }
+
+    #[test]
+    fn colored_summary() {
+        let lines = render_summary(&result(), true);
+        assert_eq!(lines[1], "check           \x1b[32mok    \x1b[0m  4s");
+        assert_eq!(lines[2], "test & release  \x1b[31mFAILED\x1b[0m  1m15s");
+        assert_eq!(lines[3], "\x1b[31m1 of 2 jobs failed\x1b[0m in 1m20s");
+        let res = ExecutionResult {
+            min_pass_ratio: Some(0.5),
+            ..result()
+        };
+        assert_eq!(
+            render_summary(&res, true)[3],
+            "\x1b[33m1 of 2 jobs failed, within the allowed failures\x1b[0m in 1m20s"
+        );
+    }
+
+    #[test]
+    fn skipped_summary() {
+        let res = ExecutionResult {
+            skipped: Some("commit message contains [skip ci]".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_summary(&res, true),
+            vec!["Pipeline skipped: commit message contains [skip ci]"]
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::milliseconds(300)), "0s");
+        assert_eq!(format_duration(Duration::seconds(59)), "59s");
+        assert_eq!(format_duration(Duration::seconds(3600)), "60m00s");
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.num_seconds();
+    match secs {
+        s if s < 60 => format!("{}s", s.max(0)),
+        s => format!("{}m{:02}s", s / 60, s % 60),
+    }
+}
+
+/// Renders a human-readable summary of `res`: one line per job, with its status & duration,
+/// then the overall result. If `color` is set, statuses are colored using ANSI escapes.
+pub fn render_summary(res: &ExecutionResult, color: bool) -> Vec<String> {
+    if let Some(reason) = &res.skipped {
+        return vec![format!("Pipeline skipped: {}", reason)];
+    }
+    let width = res
+        .job_results
+        .iter()
+        .map(|j| j.name.chars().count())
+        .chain(Some("JOB".len()))
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![format!("{:width$}  {:6}  DURATION", "JOB", "STATUS")];
+    for j in &res.job_results {
+        let status = match (j.success, j.skipped) {
+            (true, _) => paint("ok    ", Color::Green, color),
+            (false, true) => paint("SKIP  ", Color::Yellow, color),
+            (false, false) => paint("FAILED", Color::Red, color),
+        };
+        lines.push(format!(
+            "{:width$}  {}  {}",
+            j.name,
+            status,
+            format_duration(j.duration())
+        ));
+    }
+    let failed = res.job_results.iter().filter(|j| !j.success).count();
+    let total = format_duration(res.end_date - res.start_date);
+    lines.push(match failed {
+        0 => format!(
+            "{} in {}",
+            paint(
+                &format!("{} job(s) succeeded", res.job_results.len()),
+                Color::Green,
+                color
+            ),
+            total
+        ),
+        n if res.overall_success() => format!(
+            "{} in {}",
+            paint(
+                &format!(
+                    "{} of {} jobs failed, within the allowed failures",
+                    n,
+                    res.job_results.len()
+                ),
+                Color::Yellow,
+                color
+            ),
+            total
+        ),
+        n => format!(
+            "{} in {}",
+            paint(
+                &format!("{} of {} jobs failed", n, res.job_results.len()),
+                Color::Red,
+                color
+            ),
+            total
+        ),
+    });
+    lines
+}
+
+/// Prints the [summary](render_summary) of `res` on stdout, [colored](use_color) if it is a terminal
+pub fn print_summary(res: &ExecutionResult) {
+    for line in render_summary(res, use_color(&stdout())) {
+        println!("{}", line);
+    }
+}
+
+/// Prints the summary of `res` like [print_summary] does, but on stderr
+pub fn eprint_summary(res: &ExecutionResult) {
+    for line in render_summary(res, use_color(&stderr())) {
+        eprintln!("{}", line);
+    }
+}
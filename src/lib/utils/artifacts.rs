@@ -0,0 +1,248 @@
+use std::fs::{copy, create_dir, create_dir_all, metadata, symlink_metadata};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use glob::{glob, Pattern};
+use log::{debug, warn};
+
+use crate::utils::{check_relative, resolves_inside};
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, File};
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    use crate::utils::artifacts::{collect_artifacts, copy_artifacts};
+
+    fn touch(root: &Path, p: &str) {
+        let p = root.join(p);
+        create_dir_all(p.parent().unwrap()).expect("could not create dir");
+        File::create(p).expect("could not create file");
+    }
+
+    fn sample() -> TempDir {
+        let dir = TempDir::new("fake-ci-artifacts").expect("could not create temp dir");
+        for f in [
+            "target/release/app",
+            "target/release/app.d",
+            "report.xml",
+            "src/main.rs",
+        ] {
+            touch(dir.path(), f);
+        }
+        dir
+    }
+
+    #[test]
+    fn collect() {
+        let dir = sample();
+        let files = collect_artifacts(
+            dir.path(),
+            &["target/release/app".to_string(), "*.xml".to_string()],
+        )
+        .expect("could not collect artifacts");
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("report.xml"),
+                PathBuf::from("target/release/app")
+            ]
+        );
+        assert!(collect_artifacts(dir.path(), &["../*".to_string()]).is_err());
+        assert!(collect_artifacts(dir.path(), &["/etc/passwd".to_string()]).is_err());
+    }
+
+    #[test]
+    fn copy() {
+        let dir = sample();
+        let dest = TempDir::new("fake-ci-artifacts-dest").expect("could not create temp dir");
+        let files = vec![PathBuf::from("target/release/app")];
+        copy_artifacts(dir.path(), &files, dest.path()).expect("could not copy artifacts");
+        assert!(dest.path().join("target/release/app").is_file());
+    }
+
+    #[test]
+    fn symlinks() {
+        let dir = sample();
+        let outside = TempDir::new("fake-ci-host-files").expect("could not create temp dir");
+        touch(outside.path(), "id_rsa");
+        symlink(outside.path().join("id_rsa"), dir.path().join("key")).unwrap();
+        symlink(outside.path(), dir.path().join("linked")).unwrap();
+        symlink(
+            dir.path().join("report.xml"),
+            dir.path().join("report-link.xml"),
+        )
+        .unwrap();
+        let files = collect_artifacts(
+            dir.path(),
+            &[
+                "key".to_string(),
+                "linked/*".to_string(),
+                "*.xml".to_string(),
+            ],
+        )
+        .expect("could not collect artifacts");
+        // links inside the repository are fine
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("report-link.xml"),
+                PathBuf::from("report.xml")
+            ]
+        );
+        assert!(copy_artifacts(dir.path(), &[PathBuf::from("key")], outside.path()).is_err());
+
+        // nor can the destination lead out of its dir
+        let dest = TempDir::new("fake-ci-artifacts-dest").expect("could not create temp dir");
+        symlink(outside.path(), dest.path().join("target")).unwrap();
+        let files = vec![PathBuf::from("target/release/app")];
+        assert!(copy_artifacts(dir.path(), &files, dest.path()).is_err());
+        assert!(!outside.path().join("release").exists());
+    }
+
+    #[cfg(feature = "artifacts")]
+    #[test]
+    fn archive() {
+        use flate2::read::GzDecoder;
+
+        use crate::utils::artifacts::archive_artifacts;
+
+        let dir = sample();
+        let dest = TempDir::new("fake-ci-artifacts-dest").expect("could not create temp dir");
+        let files = collect_artifacts(
+            dir.path(),
+            &["target/release/*".to_string(), "report.xml".to_string()],
+        )
+        .expect("could not collect artifacts");
+        let archive = dest.path().join("archives/build.tar.gz");
+        archive_artifacts(dir.path(), &files, &archive).expect("could not archive artifacts");
+        assert!(archive.is_file());
+        let mut tar = tar::Archive::new(GzDecoder::new(
+            File::open(&archive).expect("could not open archive"),
+        ));
+        let mut entries: Vec<String> = tar
+            .entries()
+            .expect("could not read archive")
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec!["report.xml", "target/release/app", "target/release/app.d"]
+        );
+    }
+}
+
+/// Resolves the artifact glob `patterns` into a sorted list of files, relative to `root`.
+/// `root` is the repository, mounted as `/code` in containers.
+pub fn collect_artifacts(root: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for pattern in patterns {
+        check_relative(Path::new(pattern))?;
+        let full = Path::new(&Pattern::escape(&root.display().to_string())).join(pattern);
+        for entry in glob(&full.display().to_string())? {
+            let path = entry?;
+            if !path.is_file() {
+                continue;
+            }
+            if !resolves_inside(root, &path)? {
+                warn!(
+                    "Skipping artifact {}: it links out of the repository",
+                    path.display()
+                );
+                continue;
+            }
+            files.push(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+    files.sort();
+    files.dedup();
+    debug!("collected artifacts: {:?}", files);
+    Ok(files)
+}
+
+/// How much `files`, relative to `root`, weigh all together, in bytes
+pub fn artifacts_size(root: &Path, files: &[PathBuf]) -> Result<u64> {
+    let mut size = 0;
+    for f in files {
+        size += metadata(root.join(f))?.len();
+    }
+    Ok(size)
+}
+
+/// Copies `files`, relative to `root`, into `dest`, keeping the directory structure. Errors if a
+/// file, or where it would be copied, links out of its dir
+pub fn copy_artifacts(root: &Path, files: &[PathBuf], dest: &Path) -> Result<()> {
+    create_dir_all(dest)?;
+    for f in files {
+        check_relative(f)?;
+        let from = root.join(f);
+        if !resolves_inside(root, &from)? {
+            return Err(anyhow!(
+                "Cannot copy {}: it links out of its dir",
+                f.display()
+            ));
+        }
+        // each dir is created in turn, so that none is created through a symlink
+        let mut to = dest.to_path_buf();
+        for c in f.components() {
+            if to != dest {
+                match symlink_metadata(&to) {
+                    Err(_) => create_dir(&to)?,
+                    Ok(m) if m.is_dir() => {}
+                    Ok(_) => {
+                        return Err(anyhow!(
+                            "Cannot copy {}: {} is not a dir",
+                            f.display(),
+                            to.display()
+                        ))
+                    }
+                }
+            }
+            to.push(c);
+        }
+        if symlink_metadata(&to).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(anyhow!("Cannot copy {}: it is a symlink", f.display()));
+        }
+        copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "artifacts")]
+/// Packs `files`, relative to `root`, into a gzipped tarball at `archive`.
+/// Entries keep their path relative to `root`.
+pub fn archive_artifacts(root: &Path, files: &[PathBuf], archive: &Path) -> Result<()> {
+    use std::fs::File;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    if let Some(parent) = archive.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut tar = tar::Builder::new(GzEncoder::new(
+        File::create(archive)?,
+        Compression::default(),
+    ));
+    for f in files {
+        tar.append_path_with_name(root.join(f), f)?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "artifacts"))]
+/// Would pack the artifacts in a tarball, but fake-ci was built without the `artifacts` feature
+pub fn archive_artifacts(_root: &Path, _files: &[PathBuf], archive: &Path) -> Result<()> {
+    use anyhow::anyhow;
+
+    Err(anyhow!(
+        "Cannot create {}: fake-ci was built without the `artifacts` feature",
+        archive.display()
+    ))
+}
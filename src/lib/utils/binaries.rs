@@ -0,0 +1,122 @@
+use std::process::Command;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::runtime::Runtime;
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::process::Command;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::utils::binaries::Binaries;
+    use crate::utils::runtime::Runtime;
+
+    fn command_line(cmd: &Command) -> Vec<&OsStr> {
+        Some(cmd.get_program())
+            .into_iter()
+            .chain(cmd.get_args())
+            .collect()
+    }
+
+    #[test]
+    fn commands() {
+        let b = Binaries::default();
+        assert_eq!(command_line(&b.runtime_command()), vec!["docker"]);
+        assert_eq!(command_line(&b.git_command()), vec!["git"]);
+
+        let b: Binaries = serde_yaml::from_str("runtime: podman").unwrap();
+        assert_eq!(b.runtime, Runtime::Podman);
+        let mut cmd = b.runtime_command();
+        cmd.args(["ps", "-a"]);
+        assert_eq!(command_line(&cmd), vec!["podman", "ps", "-a"]);
+
+        let b: Binaries = serde_yaml::from_str(
+            "runtime: podman\nruntime_path: sudo /opt/docker/bin/docker\ngit_path: /usr/local/bin/git",
+        )
+        .unwrap();
+        let mut cmd = b.runtime_command();
+        cmd.arg("ps");
+        assert_eq!(
+            command_line(&cmd),
+            vec!["sudo", "/opt/docker/bin/docker", "ps"]
+        );
+        assert_eq!(command_line(&b.git_command()), vec!["/usr/local/bin/git"]);
+    }
+}
+
+lazy_static! {
+    static ref BINARIES: RwLock<Binaries> = RwLock::new(Binaries::default());
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+/// The external programs fake-ci runs. By default, `docker` & `git`, from the `PATH`
+pub struct Binaries {
+    #[serde(default)]
+    /// The container runtime
+    pub runtime: Runtime,
+    /// Runs the container runtime with this command instead, which can have arguments.
+    /// Ex: `/opt/docker/bin/docker`, `sudo docker`
+    pub runtime_path: Option<String>,
+    /// Path of the `git` binary
+    pub git_path: Option<String>,
+    #[serde(default)]
+    /// Podman only: relabel the volumes for SELinux, with the `Z` option
+    pub selinux_relabel: bool,
+    #[serde(default)]
+    /// Podman only: map fake-ci's user in the containers, with `--userns=keep-id`
+    pub keep_user_id: bool,
+}
+
+/// A command running `program`, which can have arguments
+fn command(program: &str) -> Command {
+    let mut words = program.split_whitespace();
+    let mut cmd = Command::new(words.next().unwrap_or(program));
+    cmd.args(words);
+    cmd
+}
+
+impl Binaries {
+    /// A command running the container runtime, to add arguments to
+    pub fn runtime_command(&self) -> Command {
+        match (&self.runtime_path, self.runtime) {
+            (Some(path), _) => command(path),
+            (None, runtime) => command(runtime.program()),
+        }
+    }
+
+    /// A command running `git`, to add arguments to
+    pub fn git_command(&self) -> Command {
+        command(self.git_path.as_deref().unwrap_or("git"))
+    }
+}
+
+/// Sets the binaries used by fake-ci, for the whole process
+pub fn set_binaries(binaries: Binaries) {
+    *BINARIES.write().unwrap_or_else(|e| e.into_inner()) = binaries;
+}
+
+/// The [configured](set_binaries) binaries
+pub fn binaries() -> Binaries {
+    BINARIES.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// A command running the [configured](set_binaries) container runtime
+pub fn docker_command() -> Command {
+    BINARIES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .runtime_command()
+}
+
+/// A command running the [configured](set_binaries) `git`
+pub fn git_command() -> Command {
+    BINARIES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .git_command()
+}
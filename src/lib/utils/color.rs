@@ -0,0 +1,88 @@
+use std::env::var_os;
+use std::ffi::OsStr;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::utils::color::{color_wanted, paint, Color};
+
+    #[test]
+    fn painting() {
+        assert_eq!(paint("ok", Color::Green, false), "ok");
+        assert_eq!(paint("ok", Color::Green, true), "\x1b[32mok\x1b[0m");
+        assert_eq!(paint("FAILED", Color::Red, true), "\x1b[31mFAILED\x1b[0m");
+        assert_eq!(paint("meh", Color::Yellow, true), "\x1b[33mmeh\x1b[0m");
+    }
+
+    #[test]
+    fn detection() {
+        assert!(color_wanted(true, None, false));
+        // piped, or to a file
+        assert!(!color_wanted(false, None, false));
+        // --no-color
+        assert!(!color_wanted(true, None, true));
+        // NO_COLOR only counts when non-empty
+        assert!(!color_wanted(true, Some("1".as_ref()), false));
+        assert!(color_wanted(true, Some("".as_ref()), false));
+    }
+}
+
+/// Set with [set_no_color]
+static NO_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Envvar which, when set to anything but an empty string, does the same as [set_no_color].
+/// See <https://no-color.org>
+pub const NO_COLOR_ENV: &str = "NO_COLOR";
+
+/// Disables colors in all of fake-ci's terminal output, even on terminals
+pub fn set_no_color(no_color: bool) {
+    NO_COLOR.store(no_color, Ordering::Relaxed);
+}
+
+fn color_wanted(terminal: bool, no_color_env: Option<&OsStr>, no_color: bool) -> bool {
+    terminal && !no_color && no_color_env.is_none_or(|v| v.is_empty())
+}
+
+/// Should the output written to `stream` be colored? Only if it is a terminal, and colors
+/// weren't [disabled](set_no_color), by flag or [envvar](NO_COLOR_ENV)
+pub fn use_color<T: IsTerminal>(stream: &T) -> bool {
+    color_wanted(
+        stream.is_terminal(),
+        var_os(NO_COLOR_ENV).as_deref(),
+        NO_COLOR.load(Ordering::Relaxed),
+    )
+}
+
+/// The colors fake-ci uses
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Color {
+    /// Successes
+    Green,
+    /// Failures
+    Red,
+    /// Failures which don't fail the pipeline
+    Yellow,
+}
+
+impl Color {
+    fn code(&self) -> &'static str {
+        match self {
+            Color::Green => "\x1b[32m",
+            Color::Red => "\x1b[31m",
+            Color::Yellow => "\x1b[33m",
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
+/// Wraps `s` in the ANSI escapes of `color` if `enabled`, usually from [use_color]
+pub fn paint(s: &str, color: Color, enabled: bool) -> String {
+    match enabled {
+        true => format!("{}{}{}", color.code(), s, RESET),
+        false => s.to_string(),
+    }
+}
@@ -0,0 +1,134 @@
+use std::cell::RefCell;
+use std::process::{Command, ExitStatus, Output};
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::mask;
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+
+    use pretty_assertions::assert_eq;
+
+    use crate::utils::commands::{command_line, print_commands, run_or_print};
+
+    #[test]
+    fn command_lines() {
+        let mut cmd = Command::new("docker");
+        cmd.args([
+            "run",
+            "-e",
+            "TOKEN=s3cr3t",
+            "--name=c",
+            "busybox",
+            "sh",
+            "-c",
+            "echo hi",
+        ]);
+        assert_eq!(
+            command_line(&cmd, &[]),
+            "docker run -e 'TOKEN=***' --name=c busybox sh -c 'echo hi'"
+        );
+        let mut cmd = Command::new("docker");
+        cmd.args(["run", "registry.example.org/s3cr3t/app"]);
+        assert_eq!(
+            command_line(&cmd, &["s3cr3t".to_string()]),
+            "docker run 'registry.example.org/***/app'"
+        );
+    }
+
+    #[test]
+    fn printed() {
+        let printed = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&printed);
+        print_commands(Some(Arc::new(move |c: &str| {
+            sink.lock().unwrap().push(c.to_string())
+        })));
+        let out = run_or_print(Command::new("false").arg("--now"), &[], |c| Ok(c.output()?));
+        print_commands(None);
+        assert!(out.expect("could not print").status.success());
+        assert_eq!(*printed.lock().unwrap(), vec!["false --now"]);
+
+        let out = run_or_print(&mut Command::new("false"), &[], |c| Ok(c.output()?));
+        assert!(!out.expect("could not run").status.success());
+    }
+}
+
+/// Told the command lines which would have run, when they are [printed](print_commands)
+pub type CommandPrinter = Arc<dyn Fn(&str) + Send + Sync>;
+
+thread_local! {
+    /// Set with [print_commands]
+    static PRINTER: RefCell<Option<CommandPrinter>> = const { RefCell::new(None) };
+}
+
+/// Gives the commands the pipelines run by the current thread would run to `printer`, rather
+/// than running them: docker's, and the host jobs'. They succeed, without any output, so that
+/// the rest of the pipeline still happens: images are resolved, jobs ordered and arguments
+/// built. The commands only reading the repository, such as `git log`, still run. `None` runs
+/// the commands again.
+pub fn print_commands(printer: Option<CommandPrinter>) {
+    PRINTER.with(|p| *p.borrow_mut() = printer);
+}
+
+/// The [printer](print_commands) of the current thread, to hand over to the threads it starts
+pub(crate) fn command_printer() -> Option<CommandPrinter> {
+    PRINTER.with(|p| p.borrow().clone())
+}
+
+/// `cmd`, as it would be typed in a shell, without the `masked` values. The values of the
+/// envvars given with `-e` are hidden too, as they may be secrets
+fn command_line(cmd: &Command, masked: &[String]) -> String {
+    let mut words = vec![cmd.get_program().to_string_lossy().to_string()];
+    let mut env_value = false;
+    for arg in cmd.get_args() {
+        let arg = mask(&arg.to_string_lossy(), masked);
+        words.push(match (env_value, arg.split_once('=')) {
+            (true, Some((k, _))) => format!("{}=***", k),
+            _ => arg.to_string(),
+        });
+        env_value = arg == "-e";
+    }
+    words
+        .iter()
+        .map(|w| quote(w))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// `word`, quoted if a shell would read it otherwise
+fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+    match plain {
+        true => word.to_string(),
+        false => shlex::try_quote(word)
+            .map(|q| q.to_string())
+            .unwrap_or_else(|_| word.to_string()),
+    }
+}
+
+/// Runs `cmd` with `run`, unless the commands are [printed](print_commands): then it is only
+/// printed, without the `masked` values, and succeeds without any output
+pub(crate) fn run_or_print(
+    cmd: &mut Command,
+    masked: &[String],
+    run: impl FnOnce(&mut Command) -> Result<Output>,
+) -> Result<Output> {
+    match command_printer() {
+        Some(printer) => {
+            printer(&command_line(cmd, masked));
+            Ok(Output {
+                status: ExitStatus::default(),
+                stdout: vec![],
+                stderr: vec![],
+            })
+        }
+        None => run(cmd),
+    }
+}
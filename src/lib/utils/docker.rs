@@ -1,14 +1,27 @@
-use std::env::current_dir;
-use std::io::Write;
+use std::cell::RefCell;
+use std::env::{current_dir, var};
+use std::io::{stderr, stdout, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use log::{debug, error};
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tempdir::TempDir;
 
 use crate::conf::FakeCIDockerBuild;
-use crate::utils::trim_newline;
-use crate::Env;
+use crate::error::FakeCiError;
+use crate::utils::binaries::{binaries, docker_command, Binaries};
+use crate::utils::commands::run_or_print;
+use crate::utils::runtime::Runtime;
+use crate::utils::{is_powershell, private_dir, shell_command, trim_newline, write_private};
+use crate::{mask, Env};
 
 #[cfg(test)]
 mod tests {
@@ -16,14 +29,28 @@ mod tests {
     use std::env::current_dir;
     use std::fs::{remove_file, File};
     use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
 
+    use chrono::{TimeZone, Utc};
     use pretty_assertions::{assert_eq, assert_ne};
     use tempdir::TempDir;
 
-    use crate::conf::FakeCIDockerBuild;
-    use crate::utils::docker::{docker_remove_image, rng_docker_chars};
-    use crate::utils::tests::with_dir;
-    use crate::{build_image, docker_remove_container, run_from_image, run_in_container, Env};
+    use crate::conf::{FakeCIDockerBuild, FakeCIJob};
+    use crate::error::FakeCiError;
+    use crate::utils::binaries::Binaries;
+    use crate::utils::docker::{
+        build_args, build_image, docker_cmd, docker_failure, docker_remove_image, exec_args,
+        inherit_docker_config, keep_alive, no_such_container, parse_build_progress, parse_created,
+        rng_docker_chars, run_args, run_in_container, run_in_container_with_input, seed_names,
+        tee_output, BuildSecrets, ExecOptions, RunOptions, KEEP_ALIVE,
+    };
+    use crate::utils::runtime::Runtime;
+    use crate::utils::tests::{get_sample_resource_file, with_dir};
+    use crate::{docker_remove_container, run_from_image, Env};
 
     #[test]
     fn docker_build() {
@@ -38,14 +65,649 @@ mod tests {
                 build_args: None,
                 name: Some("fakeci-build-image-test".to_string()),
                 privileged: false,
+                timings: false,
+                secrets: vec![],
             };
-            let image = build_image(&config).expect("Could not build image");
+            let image = build_image(&config, None, Path::new("."), &Env::new())
+                .expect("Could not build image");
             assert_eq!(image, "fakeci-build-image-test");
             let _ = docker_remove_image(&image);
             let _ = remove_file("Dockerfile");
         });
     }
 
+    #[test]
+    fn build_secrets() {
+        let _ = pretty_env_logger::try_init();
+        let tmp_dir = TempDir::new("dbuild-secrets").expect("could not create temp dir");
+        std::fs::write(
+            tmp_dir.path().join("Dockerfile"),
+            "FROM busybox\n\
+RUN --mount=type=secret,id=TOKEN test \"$(cat /run/secrets/TOKEN)\" = s3cr3t && touch /built\n",
+        )
+        .expect("could not write the Dockerfile");
+        let config = FakeCIDockerBuild {
+            dockerfile: None,
+            context: None,
+            build_args: None,
+            name: Some("fakeci-build-secrets-test".to_string()),
+            privileged: false,
+            timings: false,
+            secrets: vec!["TOKEN".to_string()],
+        };
+        let secrets = Env::from([("TOKEN".to_string(), "s3cr3t".to_string())]);
+        let image =
+            build_image(&config, None, tmp_dir.path(), &secrets).expect("Could not build image");
+        // available during the build, and nowhere to be found in the image
+        let output = docker_cmd(
+            &[
+                "run",
+                "--rm",
+                &image,
+                "sh",
+                "-c",
+                "test -e /built && ! test -e /run/secrets/TOKEN && ! grep -rqs s3cr3t /bin /etc /root",
+            ],
+            tmp_dir.path(),
+        )
+        .expect("could not run the image");
+        let history = docker_cmd(&["history", "--no-trunc", &image], tmp_dir.path())
+            .expect("could not get the history of the image");
+        let _ = docker_remove_image(&image);
+        assert!(output.status.success());
+        assert!(!String::from_utf8_lossy(&history.stdout).contains("s3cr3t"));
+
+        let err = build_image(&config, None, tmp_dir.path(), &Env::new())
+            .expect_err("built without its secret");
+        assert_eq!(
+            err.to_string(),
+            "Could not find build secret TOKEN in the executor's secrets!"
+        );
+    }
+
+    #[test]
+    fn build_secret_args() {
+        let mut config = FakeCIDockerBuild {
+            dockerfile: None,
+            context: None,
+            build_args: None,
+            name: None,
+            privileged: false,
+            timings: false,
+            secrets: vec![],
+        };
+        let none = BuildSecrets::write(&config, &Env::new()).expect("could not write no secrets");
+        assert!(none.args.is_empty());
+
+        config.secrets = vec!["TOKEN".to_string()];
+        let secrets = Env::from([("TOKEN".to_string(), "s3cr3t".to_string())]);
+        let written = BuildSecrets::write(&config, &secrets).expect("could not write secrets");
+        assert_eq!(written.values, vec!["s3cr3t"]);
+        let (id, src) = written.args[0].split_once(",src=").unwrap();
+        assert_eq!(id, "--secret=id=TOKEN");
+        assert_eq!(std::fs::read_to_string(src).unwrap(), "s3cr3t");
+        // only readable by the user running fake-ci
+        let mode = |p: &Path| p.metadata().unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(Path::new(src)), 0o600);
+        assert_eq!(mode(Path::new(src).parent().unwrap()), 0o700);
+        let args = build_args(&config, "img", None, &written.args);
+        // the context stays last
+        assert_eq!(
+            args[args.len() - 2..],
+            [written.args[0].clone(), ".".to_string()]
+        );
+        let src = src.to_string();
+        drop(written);
+        assert!(!Path::new(&src).exists());
+    }
+
+    #[test]
+    fn inherited_config() {
+        let user = TempDir::new("fake-ci-docker-config").expect("could not create temp dir");
+        let auth = TempDir::new("fake-ci-registry-auth").expect("could not create temp dir");
+        std::fs::create_dir(user.path().join("contexts")).unwrap();
+        std::fs::write(
+            user.path().join("config.json"),
+            r#"{
+  "auths": {"other.example.org": {"auth": "czNjcjN0"}},
+  "credsStore": "desktop",
+  "credHelpers": {"gcr.io": "gcloud", "registry.example.org": "pass"},
+  "currentContext": "remote",
+  "proxies": {"default": {"httpProxy": "http://proxy:3128"}}
+}"#,
+        )
+        .unwrap();
+        inherit_docker_config(user.path(), auth.path(), "registry.example.org")
+            .expect("could not inherit the config");
+        let config: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(auth.path().join("config.json")).unwrap())
+                .unwrap();
+        assert_eq!(
+            config,
+            serde_json::json!({
+                "credHelpers": {"gcr.io": "gcloud"},
+                "currentContext": "remote",
+                "proxies": {"default": {"httpProxy": "http://proxy:3128"}}
+            })
+        );
+        assert_eq!(
+            std::fs::read_link(auth.path().join("contexts")).unwrap(),
+            user.path().join("contexts")
+        );
+        assert!(!auth.path().join("cli-plugins").exists());
+
+        // no config of the user's
+        let auth = TempDir::new("fake-ci-registry-auth").expect("could not create temp dir");
+        inherit_docker_config(auth.path(), auth.path(), "registry.example.org").unwrap();
+        assert!(!auth.path().join("config.json").exists());
+    }
+
+    #[test]
+    fn daemon_unreachable() {
+        let down = b"docker: Cannot connect to the Docker daemon at unix:///var/run/docker.sock. \
+Is the docker daemon running?.\nSee 'docker run --help'.\n";
+        let err = docker_failure(down, "Could not build docker image img".to_string());
+        assert!(matches!(err, FakeCiError::DaemonUnreachable));
+        assert_eq!(
+            err.to_string(),
+            "Docker daemon is not reachable — is it running?"
+        );
+        let podman = b"Error: Cannot connect to Podman. Please verify your connection\n";
+        assert!(matches!(
+            docker_failure(podman, String::new()),
+            FakeCiError::DaemonUnreachable
+        ));
+
+        let err = docker_failure(
+            b"failed to solve: dockerfile parse error\n",
+            "Could not build docker image img".to_string(),
+        );
+        assert_eq!(
+            err.to_string(),
+            "docker error: Could not build docker image img"
+        );
+    }
+
+    #[test]
+    fn missing_container() {
+        assert!(no_such_container(
+            b"Error response from daemon: No such container: fake-ci-nope\n"
+        ));
+        assert!(no_such_container(
+            b"Error: no container with name or ID \"fake-ci-nope\" found: no such container\n"
+        ));
+        assert!(!no_such_container(
+            b"Error response from daemon: removal of container x is already in progress\n"
+        ));
+    }
+
+    #[test]
+    fn remove_running_container() {
+        let _ = pretty_env_logger::try_init();
+        let cname = format!("fake-ci-tests-{}", rng_docker_chars(4));
+        let opts = RunOptions {
+            detach: true,
+            mount_source: false,
+            ..Default::default()
+        };
+        let o = run_from_image("busybox", &cname, KEEP_ALIVE, &opts).expect("could not run");
+        assert!(o.status.success());
+        docker_remove_container(&cname).expect("could not remove the running container");
+        let inspect = docker_cmd(&["inspect", &cname], Path::new(".")).unwrap();
+        assert!(!inspect.status.success());
+        assert!(no_such_container(&inspect.stderr));
+    }
+
+    #[test]
+    fn created_dates() {
+        let containers = parse_created(
+            "/fake-ci-build-a1b2 2021-11-29T12:00:00.123456789Z\n\
+             fake-ci-test-c3d4 2021-11-28 10:30:00.5 +0100 CET\n\n",
+        )
+        .expect("could not parse");
+        assert_eq!(
+            containers,
+            vec![
+                (
+                    "fake-ci-build-a1b2".to_string(),
+                    Utc.with_ymd_and_hms(2021, 11, 29, 12, 0, 0).unwrap()
+                        + chrono::Duration::nanoseconds(123456789)
+                ),
+                (
+                    "fake-ci-test-c3d4".to_string(),
+                    Utc.with_ymd_and_hms(2021, 11, 28, 9, 30, 0).unwrap()
+                        + chrono::Duration::milliseconds(500)
+                ),
+            ]
+        );
+        assert!(parse_created("fake-ci-build-a1b2 yesterday").is_err());
+        assert!(parse_created("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn remove_missing_container() {
+        let cname = format!("fake-ci-tests-{}", rng_docker_chars(4));
+        assert!(docker_remove_container(&cname).is_ok());
+        // twice in a row
+        assert!(docker_remove_container(&cname).is_ok());
+    }
+
+    #[test]
+    fn build_progress() {
+        let log = get_sample_resource_file("buildkit_progress.txt").expect("not found");
+        let steps = parse_build_progress(&log);
+        let summary: Vec<(&str, i64, bool)> = steps
+            .iter()
+            .map(|s| (s.name.as_str(), s.duration_ms, s.cached))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("[internal] load build definition from Dockerfile", 0, false),
+                ("[internal] load metadata for docker.io/library/rust:1.75", 1200, false),
+                ("[internal] load .dockerignore", 0, false),
+                ("[1/4] FROM docker.io/library/rust:1.75@sha256:87f3b2f93b82995443a1a558e234212d7a2b72b4c9b2e0ac28e7f0f8dba1d2b5", 0, true),
+                ("[internal] load build context", 100, false),
+                ("[2/4] WORKDIR /app", 100, false),
+                ("[3/4] COPY . .", 300, false),
+                ("[4/4] RUN cargo build --release", 41900, false),
+                ("exporting to image", 1800, false),
+            ]
+        );
+        // a failed step never finishes
+        let failed =
+            "#1 [1/2] RUN false\n#1 ERROR: process \"/bin/sh -c false\" did not complete\n";
+        assert!(parse_build_progress(failed).is_empty());
+
+        let mut config = FakeCIDockerBuild {
+            dockerfile: None,
+            context: None,
+            build_args: None,
+            name: None,
+            privileged: false,
+            timings: false,
+            secrets: vec![],
+        };
+        assert!(!build_args(&config, "img", None, &[]).contains(&"--progress=plain".to_string()));
+        config.timings = true;
+        assert!(build_args(&config, "img", None, &[]).contains(&"--progress=plain".to_string()));
+    }
+
+    #[test]
+    fn platform_args() {
+        let config = FakeCIDockerBuild {
+            dockerfile: None,
+            context: None,
+            build_args: None,
+            name: None,
+            privileged: false,
+            timings: false,
+            secrets: vec![],
+        };
+        let args = build_args(&config, "img", None, &[]);
+        assert!(!args.iter().any(|a| a.starts_with("--platform")));
+        let args = build_args(&config, "img", Some("linux/amd64"), &[]);
+        assert!(args.contains(&"--platform=linux/amd64".to_string()));
+
+        let args = run_args(
+            "busybox",
+            "c",
+            "sh",
+            &RunOptions::default(),
+            &Binaries::default(),
+        )
+        .unwrap();
+        assert!(!args.iter().any(|a| a.starts_with("--platform")));
+        let opts = RunOptions {
+            platform: Some("linux/amd64".to_string()),
+            ..Default::default()
+        };
+        let args = run_args("busybox", "c", "sh", &opts, &Binaries::default()).unwrap();
+        let platform = args.iter().position(|a| a == "--platform=linux/amd64");
+        let image = args.iter().position(|a| a == "busybox");
+        assert!(platform.is_some());
+        assert!(platform < image);
+    }
+
+    #[test]
+    fn pull_args() {
+        let args = run_args(
+            "busybox",
+            "c",
+            "sh",
+            &RunOptions::default(),
+            &Binaries::default(),
+        )
+        .unwrap();
+        assert!(args.contains(&"--pull=always".to_string()));
+        let opts = RunOptions {
+            pull: false,
+            ..Default::default()
+        };
+        let args = run_args("busybox", "c", "sh", &opts, &Binaries::default()).unwrap();
+        assert!(args.contains(&"--pull=never".to_string()));
+        assert!(!args.contains(&"--pull=always".to_string()));
+    }
+
+    #[test]
+    fn extra_hosts_args() {
+        let mut opts = RunOptions {
+            extra_hosts: vec!["db:127.0.0.1".to_string(), "v6:::1".to_string()],
+            ..Default::default()
+        };
+        let args = run_args("busybox", "c", "sh", &opts, &Binaries::default()).unwrap();
+        assert!(args.contains(&"--add-host=db:127.0.0.1".to_string()));
+        assert!(args.contains(&"--add-host=v6:::1".to_string()));
+        for invalid in ["db", "db:", ":127.0.0.1"] {
+            opts.extra_hosts = vec![invalid.to_string()];
+            let err = run_args("busybox", "c", "sh", &opts, &Binaries::default()).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "configuration error: Invalid extra host {}: expected HOST:IP",
+                    invalid
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn digest_args() {
+        let image =
+            "busybox@sha256:7b3ccabffc97de872a30dfd234fd972a66d247c8cfc69b0550f276481852627c";
+        let args = run_args(
+            image,
+            "c",
+            "sh",
+            &RunOptions::default(),
+            &Binaries::default(),
+        )
+        .unwrap();
+        assert_eq!(args[args.len() - 2], image);
+    }
+
+    #[test]
+    fn extra_args() {
+        let opts = RunOptions {
+            extra_args: vec!["--shm-size=256m".to_string()],
+            ..Default::default()
+        };
+        let args = run_args("busybox", "c", "sh", &opts, &Binaries::default()).unwrap();
+        let extra = args.iter().position(|a| a == "--shm-size=256m");
+        let image = args.iter().position(|a| a == "busybox");
+        assert!(extra.is_some());
+        assert!(extra < image);
+    }
+
+    #[test]
+    fn podman_args() {
+        let opts = RunOptions {
+            volumes: vec!["/srv/cache:/cache:ro".to_string()],
+            privileged: true,
+            mount_source: false,
+            ..Default::default()
+        };
+        let docker = run_args("busybox", "c", "sh", &opts, &Binaries::default()).unwrap();
+        assert!(docker.contains(&"--volume=/srv/cache:/cache:ro".to_string()));
+        assert!(docker.contains(&"--privileged".to_string()));
+        let podman = Binaries {
+            runtime: Runtime::Podman,
+            selinux_relabel: true,
+            keep_user_id: true,
+            ..Default::default()
+        };
+        let args = run_args("busybox", "c", "sh", &opts, &podman).unwrap();
+        assert!(args.contains(&"--volume=/srv/cache:/cache:ro,Z".to_string()));
+        assert!(args.contains(&"--privileged".to_string()));
+        assert!(args.contains(&"--userns=keep-id".to_string()));
+        // docker ignores the podman-only settings
+        let docker = Binaries {
+            runtime: Runtime::Docker,
+            ..podman
+        };
+        let args = run_args("busybox", "c", "sh", &opts, &docker).unwrap();
+        assert!(args.contains(&"--volume=/srv/cache:/cache:ro".to_string()));
+        assert!(!args.contains(&"--userns=keep-id".to_string()));
+    }
+
+    #[test]
+    fn run_with_extra_args() {
+        let _ = pretty_env_logger::try_init();
+        let cname = format!("fake-ci-tests-{}", rng_docker_chars(4));
+        let o = run_from_image(
+            "busybox",
+            &cname,
+            KEEP_ALIVE,
+            &RunOptions {
+                extra_args: vec!["--shm-size=256m".to_string()],
+                detach: true,
+                ..Default::default()
+            },
+        );
+        assert!(o.is_ok());
+        let o = run_in_container(&cname, "df -m /dev/shm | tail -n 1");
+        let _ = docker_remove_container(&cname);
+        let o = o.unwrap();
+        assert!(o.status.success());
+        assert!(String::from_utf8_lossy(&o.stdout).contains(" 256 "));
+    }
+
+    #[test]
+    fn command_args() {
+        let args = exec_args("c", "read line; echo $line", &ExecOptions::default()).unwrap();
+        assert_eq!(
+            args,
+            vec!["exec", "-i", "c", "sh", "-c", "read line; echo $line"]
+        );
+        let opts = ExecOptions {
+            workdir: Some("/code/frontend".to_string()),
+            shell: "bash -o pipefail".to_string(),
+            ..Default::default()
+        };
+        let args = exec_args("c", "pwd", &opts).unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "exec",
+                "-i",
+                "-w",
+                "/code/frontend",
+                "c",
+                "bash",
+                "-o",
+                "pipefail",
+                "-c",
+                "pwd"
+            ]
+        );
+
+        let args = run_args(
+            "busybox",
+            "c",
+            "sh",
+            &RunOptions::default(),
+            &Binaries::default(),
+        )
+        .unwrap();
+        assert!(!args.contains(&"--detach".to_string()));
+        let opts = RunOptions {
+            detach: true,
+            ..Default::default()
+        };
+        let args = run_args("busybox", "c", KEEP_ALIVE, &opts, &Binaries::default()).unwrap();
+        assert!(args.contains(&"--detach".to_string()));
+        assert_eq!(
+            args[args.len() - 4..],
+            ["busybox", "tail", "-f", "/dev/null"]
+        );
+    }
+
+    #[test]
+    fn quoted_command() {
+        let args = run_args(
+            "busybox",
+            "c",
+            "sh -c 'echo \"hello world\" && exit 0'",
+            &RunOptions::default(),
+            &Binaries::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            args[args.len() - 4..],
+            ["busybox", "sh", "-c", "echo \"hello world\" && exit 0"]
+        );
+        assert!(run_args(
+            "busybox",
+            "c",
+            "sh -c 'unclosed",
+            &RunOptions::default(),
+            &Binaries::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn powershell_args() {
+        let opts = ExecOptions {
+            workdir: Some("C:\\code".to_string()),
+            shell: "powershell".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            exec_args("c", "Write-Output $env:CI", &opts).unwrap(),
+            vec![
+                "exec",
+                "-i",
+                "-w",
+                "C:\\code",
+                "c",
+                "powershell",
+                "-NoProfile",
+                "-NonInteractive",
+                "-Command",
+                "Write-Output $env:CI"
+            ]
+        );
+        assert_eq!(keep_alive("sh"), KEEP_ALIVE);
+        let args = run_args(
+            "mcr.microsoft.com/windows/servercore",
+            "c",
+            &keep_alive("powershell"),
+            &RunOptions::default(),
+            &Binaries::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            args[args.len() - 8..],
+            [
+                "mcr.microsoft.com/windows/servercore",
+                "powershell",
+                "-NoProfile",
+                "-NonInteractive",
+                "-Command",
+                "Start-Sleep",
+                "-Seconds",
+                "2147483647"
+            ][..]
+        );
+    }
+
+    /// A writer whose content can be read from another thread
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn seeded_names() {
+        let job: FakeCIJob = serde_yaml::from_str("name: Unit tests\nsteps: [cargo test]").unwrap();
+        seed_names(Some(42));
+        let (name, cname) = (rng_docker_chars(8), job.generate_container_name());
+        assert_eq!(name, "fakeci-uypb04a7");
+        assert_eq!(cname, "fake-ci-unit-tests-fakeci-tfia");
+        seed_names(Some(42));
+        assert_eq!(rng_docker_chars(8), name);
+        // other threads stay random
+        let other = thread::spawn(|| rng_docker_chars(8)).join().unwrap();
+        assert_ne!(other, name);
+        seed_names(None);
+        assert_ne!(rng_docker_chars(8), rng_docker_chars(8));
+    }
+
+    #[test]
+    fn forwarded_output() {
+        let (out, err) = (SharedBuf::default(), SharedBuf::default());
+        let output = tee_output(
+            Command::new("sh").args(["-c", "read line; echo \"got $line\"; echo oops >&2"]),
+            b"duck\n",
+            out.clone(),
+            err.clone(),
+        )
+        .expect("could not run sh");
+        assert!(output.status.success());
+        // captured...
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "got duck\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "oops\n");
+        // ...and forwarded
+        assert_eq!(*out.0.lock().unwrap(), b"got duck\n");
+        assert_eq!(*err.0.lock().unwrap(), b"oops\n");
+    }
+
+    #[test]
+    fn run_with_stdin() {
+        let _ = pretty_env_logger::try_init();
+        let cname = format!("fake-ci-tests-{}", rng_docker_chars(4));
+        let opts = RunOptions {
+            detach: true,
+            mount_source: false,
+            ..Default::default()
+        };
+        assert!(run_from_image("busybox", &cname, KEEP_ALIVE, &opts).is_ok());
+        let o = run_in_container_with_input(
+            &cname,
+            "read line < /dev/stdin; echo got=$line",
+            b"duck\n",
+        );
+        // nothing is piped: the command sees an empty stdin, instead of hanging
+        let empty = run_in_container(&cname, "cat");
+        let _ = docker_remove_container(&cname);
+        let o = o.unwrap();
+        assert!(o.status.success());
+        assert_eq!(String::from_utf8_lossy(&o.stdout), "got=duck\n");
+        assert_eq!(String::from_utf8_lossy(&empty.unwrap().stdout), "");
+    }
+
+    #[test]
+    fn container_reuse() {
+        let _ = pretty_env_logger::try_init();
+        let cname = format!("fake-ci-tests-{}", rng_docker_chars(4));
+        let opts = RunOptions {
+            detach: true,
+            mount_source: false,
+            ..Default::default()
+        };
+        assert!(run_from_image("busybox", &cname, KEEP_ALIVE, &opts).is_ok());
+        let codes = ["touch /tmp/reused", "test -f /tmp/reused", "exit 3", "true"]
+            .iter()
+            .map(|c| run_in_container(&cname, c).map(|o| o.status.code()))
+            .collect::<Vec<_>>();
+        let _ = docker_remove_container(&cname);
+        let codes = codes
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        // the container outlives a failed step
+        assert_eq!(codes, vec![Some(0), Some(0), Some(3), Some(0)]);
+    }
+
     #[test]
     fn run_with_env() {
         let _ = pretty_env_logger::try_init();
@@ -55,7 +717,16 @@ mod tests {
             let mut env = HashMap::new();
             env.insert("TEST_VAL".to_string(), "duck".to_string());
             let cname = format!("fake-ci-tests-{}", rng_docker_chars(4));
-            let o = run_from_image("busybox", &cname, "sh", &vec![], &env, false, false);
+            let o = run_from_image(
+                "busybox",
+                &cname,
+                KEEP_ALIVE,
+                &RunOptions {
+                    env,
+                    detach: true,
+                    ..Default::default()
+                },
+            );
             assert!(o.is_ok());
             let o = run_in_container(&cname, "echo val=$TEST_VAL");
             assert!(o.is_ok());
@@ -76,7 +747,15 @@ mod tests {
             println!("current_dir: {}", current_dir().unwrap().display());
             let vols = vec!["/var/run/docker.sock:/var/run/docker.sock".to_string()];
             let cname = format!("fake-ci-tests-{}", rng_docker_chars(4));
-            let o = run_from_image("busybox", &cname, "sh", &vols, &Env::new(), false, false);
+            let o = run_from_image(
+                "busybox",
+                &cname,
+                "sh",
+                &RunOptions {
+                    volumes: vols,
+                    ..Default::default()
+                },
+            );
             assert!(o.is_ok());
             let o = o.unwrap();
             assert!(o.status.success());
@@ -84,12 +763,26 @@ mod tests {
     }
 }
 
+/// A command keeping a [detached](RunOptions::detach) container running, until it is removed
+pub const KEEP_ALIVE: &str = "tail -f /dev/null";
+
+/// The command keeping a container running, for jobs using `shell`. [PowerShell](is_powershell)
+/// images, Windows ones especially, may not have `tail`: PowerShell then sleeps instead.
+pub fn keep_alive(shell: &str) -> String {
+    match is_powershell(shell) {
+        true => shell_command(shell, "Start-Sleep -Seconds 2147483647")
+            .map(|args| args.join(" "))
+            .unwrap_or_else(|_| KEEP_ALIVE.to_string()),
+        false => KEEP_ALIVE.to_string(),
+    }
+}
+
 pub(crate) const DOCKER_NAME_CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz-_0123456789";
 
 #[allow(dead_code)]
 pub(crate) fn get_pwd_from_image(image: &str) -> Result<String> {
     debug!("Getting pwd with command docker run --rm {} pwd", image);
-    let output = Command::new("docker")
+    let output = docker_command()
         .args(["run", "--rm", image, "pwd"])
         .output()?;
     if !output.status.success() {
@@ -101,91 +794,579 @@ pub(crate) fn get_pwd_from_image(image: &str) -> Result<String> {
     Ok(s)
 }
 
-pub(crate) fn cwd() -> Result<String> {
-    Ok(format!("{}", current_dir()?.display()))
+/// Set with [set_verbose]
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Envvar which, when set to anything but `0`, does the same as [set_verbose]
+pub const VERBOSE_ENV: &str = "FAKECI_VERBOSE_DOCKER";
+
+/// Forwards the output of docker itself (image pulls, builds, commands run in containers) to
+/// fake-ci's stdout & stderr, as it comes. It is still captured for the logs
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
 }
 
-fn docker_cmd(args: &[&str], current_dir: &str) -> Result<Output> {
-    debug!("Running in {}: docker {}", current_dir, args.join(" "));
-    Ok(Command::new("docker")
-        .args(args)
-        .current_dir(current_dir)
-        .output()?)
+fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed) || var(VERBOSE_ENV).is_ok_and(|v| !v.is_empty() && v != "0")
 }
 
-/// builds an image, returning the name of the newly built image
-pub fn build_image(config: &FakeCIDockerBuild) -> Result<String> {
-    debug!("build image called with {:?}", config);
-    let rand_name = rng_docker_chars(12);
-    let name = &config.name.as_ref().unwrap_or(&rand_name);
-    let default_context = ".".to_string();
-    let args = &[
-        "build",
-        &format!(
+/// Runs `cmd` like [Command::output], with `input` on its stdin. Its stdout & stderr are also
+/// copied to `out` & `err` as they come.
+fn tee_output<O, E>(cmd: &mut Command, input: &[u8], out: O, err: E) -> Result<Output>
+where
+    O: Write + Send + 'static,
+    E: Write + Send + 'static,
+{
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    fn tee<R: Read + Send + 'static, W: Write + Send + 'static>(
+        pipe: Option<R>,
+        mut forward: W,
+    ) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut captured = vec![];
+            let mut buf = [0; 8192];
+            if let Some(mut p) = pipe {
+                while let Ok(n @ 1..) = p.read(&mut buf) {
+                    captured.extend_from_slice(&buf[..n]);
+                    let _ = forward.write_all(&buf[..n]).and_then(|_| forward.flush());
+                }
+            }
+            captured
+        })
+    }
+    let stdout = tee(child.stdout.take(), out);
+    let stderr = tee(child.stderr.take(), err);
+    if let Some(mut stdin) = child.stdin.take() {
+        if !input.is_empty() {
+            stdin.write_all(input)?;
+        }
+    }
+    let status = child.wait()?;
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Runs the docker command `cmd`, with `input` on its stdin, and captures its output. In
+/// [verbose](set_verbose) mode, the output is also forwarded as it comes. When the commands are
+/// [printed](crate::utils::commands::print_commands), it is only printed, without the `masked`
+/// values.
+fn docker_output(cmd: &mut Command, input: &[u8], masked: &[String]) -> Result<Output> {
+    run_or_print(cmd, masked, |cmd| {
+        if verbose() {
+            return tee_output(cmd, input, stdout(), stderr());
+        }
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if !input.is_empty() {
+            child.stdin.as_mut().unwrap().write_all(input)?;
+        }
+        // closes stdin, then waits
+        Ok(child.wait_with_output()?)
+    })
+}
+
+/// What docker, or podman, says when its daemon can't be reached
+const DAEMON_UNREACHABLE: &[&str] = &[
+    "Cannot connect to the Docker daemon",
+    "Is the docker daemon running?",
+    "Cannot connect to Podman",
+    "error during connect",
+];
+
+/// Did the docker command which printed `stderr` fail to reach its daemon?
+fn daemon_unreachable(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr);
+    DAEMON_UNREACHABLE.iter().any(|s| stderr.contains(s))
+}
+
+/// The error of a docker command which failed, printing `stderr`:
+/// [FakeCiError::DaemonUnreachable] if it could not reach its daemon, else a
+/// [FakeCiError::Docker] saying `fallback`
+fn docker_failure(stderr: &[u8], fallback: String) -> FakeCiError {
+    match daemon_unreachable(stderr) {
+        true => FakeCiError::DaemonUnreachable,
+        false => FakeCiError::Docker(fallback),
+    }
+}
+
+fn docker_cmd(args: &[&str], current_dir: &Path) -> Result<Output> {
+    debug!(
+        "Running in {}: docker {}",
+        current_dir.display(),
+        args.join(" ")
+    );
+    docker_output(
+        docker_command().args(args).current_dir(current_dir),
+        &[],
+        &[],
+    )
+}
+
+/// `secret_args` are the `--secret` args of the build's [secrets](BuildSecrets)
+fn build_args(
+    config: &FakeCIDockerBuild,
+    name: &str,
+    platform: Option<&str>,
+    secret_args: &[String],
+) -> Vec<String> {
+    let mut args = vec![
+        "build".to_string(),
+        format!(
             "--file={}",
-            &config
-                .dockerfile
-                .as_ref()
-                .unwrap_or(&"Dockerfile".to_string())
+            config.dockerfile.as_deref().unwrap_or("Dockerfile")
         ),
-        "-t",
-        name,
-        config.context.as_ref().unwrap_or(&default_context),
+        "-t".to_string(),
+        name.to_string(),
     ];
-    let output = docker_cmd(args, config.context.as_ref().unwrap_or(&".".to_string()))?;
+    if let Some(platform) = platform {
+        args.push(format!("--platform={}", platform));
+    }
+    if config.timings {
+        args.push("--progress=plain".to_string());
+    }
+    args.extend_from_slice(secret_args);
+    args.push(config.context.as_deref().unwrap_or(".").to_string());
+    args
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+/// How long building an image took
+pub struct BuildTimings {
+    /// The whole build, in milliseconds
+    pub total_ms: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Its steps, in the order they started, if the build was
+    /// [timed](FakeCIDockerBuild::timings)
+    pub steps: Vec<BuildStepTiming>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// A step of a [timed](BuildTimings) build
+pub struct BuildStepTiming {
+    /// What BuildKit calls it. Ex: `[2/4] RUN cargo build --release`
+    pub name: String,
+    /// How long it took, in milliseconds
+    pub duration_ms: i64,
+    /// If it was taken from the cache, taking no time
+    pub cached: bool,
+}
+
+/// Reads the steps of a build from BuildKit's plain progress `log`, where each line belongs to
+/// a step, `#<n>`: its first one names it, and the last one is `DONE <secs>s`, or `CACHED`.
+/// The steps which didn't finish, such as a failed one, are left out
+pub fn parse_build_progress(log: &str) -> Vec<BuildStepTiming> {
+    let mut names: Vec<(&str, &str)> = vec![];
+    let mut steps: Vec<(usize, BuildStepTiming)> = vec![];
+    for line in log.lines() {
+        let (id, rest) = match line.strip_prefix('#').and_then(|l| l.split_once(' ')) {
+            Some((id, rest)) if id.chars().all(|c| c.is_ascii_digit()) => (id, rest.trim()),
+            _ => continue,
+        };
+        let index = match names.iter().position(|(i, _)| *i == id) {
+            Some(index) => index,
+            None => {
+                names.push((id, rest));
+                continue;
+            }
+        };
+        let duration_ms = match rest {
+            "CACHED" => Some(0),
+            _ => rest
+                .strip_prefix("DONE ")
+                .and_then(|d| d.strip_suffix('s'))
+                .and_then(|d| d.parse::<f64>().ok())
+                .map(|secs| (secs * 1000.0).round() as i64),
+        };
+        if let Some(duration_ms) = duration_ms {
+            steps.push((
+                index,
+                BuildStepTiming {
+                    name: names[index].1.to_string(),
+                    duration_ms,
+                    cached: rest == "CACHED",
+                },
+            ));
+        }
+    }
+    steps.sort_by_key(|(index, _)| *index);
+    steps.into_iter().map(|(_, step)| step).collect()
+}
+
+/// The [secrets](FakeCIDockerBuild::secrets) of a build, written to a temporary dir that is
+/// removed once dropped
+struct BuildSecrets {
+    /// `docker build --secret` args, one per secret
+    args: Vec<String>,
+    /// The secrets' values, to mask in the logs
+    values: Vec<String>,
+    _dir: Option<TempDir>,
+}
+
+impl BuildSecrets {
+    fn write(config: &FakeCIDockerBuild, secrets: &Env) -> Result<Self> {
+        let mut build_secrets = BuildSecrets {
+            args: vec![],
+            values: vec![],
+            _dir: None,
+        };
+        if config.secrets.is_empty() {
+            return Ok(build_secrets);
+        }
+        let dir = private_dir("fakeci-build-secrets")?;
+        for (i, secret) in config.secrets.iter().enumerate() {
+            let value = secrets.get(secret).ok_or_else(|| {
+                anyhow!(
+                    "Could not find build secret {} in the executor's secrets!",
+                    secret
+                )
+            })?;
+            let path = dir.path().join(format!("secret-{}", i));
+            write_private(&path, value)?;
+            build_secrets
+                .args
+                .push(format!("--secret=id={},src={}", secret, path.display()));
+            build_secrets.values.push(value.to_string());
+        }
+        build_secrets._dir = Some(dir);
+        Ok(build_secrets)
+    }
+}
+
+/// builds an image, returning the name of the newly built image. Its context is relative to
+/// the repository at `repo`. If a `platform` is given, the image is built for it. The
+/// [build secrets](FakeCIDockerBuild::secrets) are taken from `secrets`.
+pub fn build_image(
+    config: &FakeCIDockerBuild,
+    platform: Option<&str>,
+    repo: &Path,
+    secrets: &Env,
+) -> Result<String> {
+    build_image_timed(config, platform, repo, secrets, None).map(|(name, _)| name)
+}
+
+/// [Builds an image](build_image), returning its name, and how long it took. With an
+/// `auth_dir`, the base images are pulled with the credentials [logged into](docker_login) it
+pub fn build_image_timed(
+    config: &FakeCIDockerBuild,
+    platform: Option<&str>,
+    repo: &Path,
+    secrets: &Env,
+    auth_dir: Option<&Path>,
+) -> Result<(String, BuildTimings)> {
+    debug!("build image called with {:?}", config);
+    let rand_name = rng_docker_chars(12);
+    let name = config.name.as_ref().unwrap_or(&rand_name);
+    let build_secrets = BuildSecrets::write(config, secrets)?;
+    let args = build_args(config, name, platform, &build_secrets.args);
+    let dir = repo.join(config.context.as_deref().unwrap_or("."));
+    debug!("Running in {}: docker {}", dir.display(), args.join(" "));
+    let mut cmd = docker_command();
+    cmd.args(&args).current_dir(&dir);
+    if !config.secrets.is_empty() {
+        // the classic builder knows nothing of secrets
+        cmd.env("DOCKER_BUILDKIT", "1");
+    }
+    if let Some(dir) = auth_dir {
+        let (name, value) = binaries().runtime.auth_env(dir);
+        cmd.env(name, value);
+    }
+    let start = Instant::now();
+    let output = docker_output(&mut cmd, &[], &build_secrets.values)?;
+    // BuildKit prints its progress on stderr
+    let stderr = mask(
+        &String::from_utf8_lossy(&output.stderr),
+        &build_secrets.values,
+    );
+    let timings = BuildTimings {
+        total_ms: start.elapsed().as_millis() as i64,
+        steps: match config.timings {
+            true => parse_build_progress(&stderr),
+            false => vec![],
+        },
+    };
     if !output.status.success() {
-        error!(
-            "Error on docker build: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        return Err(anyhow!("Could not build docker image {}", args[3]));
+        error!("Error on docker build: {}", stderr);
+        return Err(docker_failure(
+            stderr.as_bytes(),
+            format!("Could not build docker image {}", name),
+        )
+        .into());
     }
 
-    Ok(name.to_string())
+    Ok((name.to_string(), timings))
+}
+
+thread_local! {
+    /// The RNG of the generated names, when [seeded](seed_names)
+    static NAMES_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Seeds the RNG of the container & image names generated by the current thread, so that they
+/// are reproducible. Meant for tests; `None` makes them random again.
+pub fn seed_names(seed: Option<u64>) {
+    NAMES_RNG.with(|rng| *rng.borrow_mut() = seed.map(StdRng::seed_from_u64));
 }
 
 pub(crate) fn rng_docker_chars(n: u8) -> String {
-    let mut rng = rand::thread_rng();
-    let rand_name = format!(
-        "fakeci-{}",
-        (0..n)
-            .map(|_| {
-                let idx = rng.gen_range(0..DOCKER_NAME_CHARSET.len());
-                DOCKER_NAME_CHARSET[idx] as char
-            })
-            .collect::<String>()
-    );
-    rand_name
+    NAMES_RNG.with(|seeded| {
+        let mut seeded = seeded.borrow_mut();
+        let mut thread_rng = rand::thread_rng();
+        let rng: &mut dyn rand::RngCore = match seeded.as_mut() {
+            Some(r) => r,
+            None => &mut thread_rng,
+        };
+        format!(
+            "fakeci-{}",
+            (0..n)
+                .map(|_| {
+                    let idx = rng.gen_range(0..DOCKER_NAME_CHARSET.len());
+                    DOCKER_NAME_CHARSET[idx] as char
+                })
+                .collect::<String>()
+        )
+    })
 }
 
 /// Removes the given image
 pub fn docker_remove_image(image: &str) -> Result<()> {
     let args = &["rmi", image];
-    let output = docker_cmd(args, &cwd()?)?;
+    let output = docker_cmd(args, &current_dir()?)?;
     if !output.status.success() {
         return Err(anyhow!("Could not remove docker image"));
     }
     Ok(())
 }
 
-/// Removes an existing container
+/// How long a container gets to exit once asked to stop, before it is killed
+pub const STOP_TIMEOUT_SECS: u64 = 2;
+
+/// What docker, or podman, says of a container which doesn't exist
+const NO_SUCH_CONTAINER: &[&str] = &["No such container", "no such container"];
+
+/// Did the docker command which printed `stderr` fail because its container doesn't exist?
+fn no_such_container(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr);
+    NO_SUCH_CONTAINER.iter().any(|s| stderr.contains(s))
+}
+
+/// Removes a container, whatever its state: if it is running, it is first asked to stop, and
+/// given [STOP_TIMEOUT_SECS] to do so, then killed. Succeeds if the container is already gone
 pub fn docker_remove_container(container: &str) -> Result<()> {
-    let args = &["rm", container];
-    let output = docker_cmd(args, &cwd()?)?;
+    let dir = current_dir()?;
+    let timeout = format!("--time={}", STOP_TIMEOUT_SECS);
+    // it may be stopped, or gone, already: removing it tells
+    let output = docker_cmd(&["stop", &timeout, container], &dir)?;
+    if !output.status.success() && daemon_unreachable(&output.stderr) {
+        return Err(FakeCiError::DaemonUnreachable.into());
+    }
+    let output = docker_cmd(&["rm", "--force", container], &dir)?;
+    if !output.status.success() && !no_such_container(&output.stderr) {
+        return Err(docker_failure(
+            &output.stderr,
+            format!("Could not remove docker container {}", container),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The containers named like those of fake-ci's jobs (`fake-ci-…`), with when they were created:
+/// those of the running jobs, those [kept on failure](crate::conf::FakeCIJob::keep_on_failure), and
+/// those left behind by a crash
+pub fn fakeci_containers() -> Result<Vec<(String, DateTime<Utc>)>> {
+    let dir = current_dir()?;
+    let output = docker_cmd(
+        &["ps", "--all", "--quiet", "--filter", "name=^fake-ci-"],
+        &dir,
+    )?;
+    if !output.status.success() {
+        return Err(
+            docker_failure(&output.stderr, "Could not list the containers".to_string()).into(),
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let ids: Vec<&str> = stdout.split_whitespace().collect();
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut args = vec!["inspect", "--format", "{{.Name}} {{.Created}}"];
+    args.extend(ids);
+    let output = docker_cmd(&args, &dir)?;
+    // one may have been removed since it was listed: the others are still printed
+    if !output.status.success() && !no_such_container(&output.stderr) {
+        return Err(docker_failure(
+            &output.stderr,
+            "Could not inspect the containers".to_string(),
+        )
+        .into());
+    }
+    parse_created(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses the output of `docker inspect --format '{{.Name}} {{.Created}}'`. Docker prints the
+/// dates as RFC 3339, podman as Go does (`2021-11-29 12:00:00.123456789 +0000 UTC`)
+fn parse_created(output: &str) -> Result<Vec<(String, DateTime<Utc>)>> {
+    output
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| {
+            let (name, created) = l
+                .trim()
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Unexpected docker inspect output: {}", l))?;
+            let created = DateTime::parse_from_rfc3339(created)
+                .or_else(|_| {
+                    // drop the zone's name, the offset says it all
+                    let offset = created.rsplit_once(' ').map_or(created, |(o, _)| o);
+                    DateTime::parse_from_str(offset, "%Y-%m-%d %H:%M:%S%.f %z")
+                })
+                .map_err(|e| anyhow!("Unexpected creation date {}: {}", created, e))?;
+            Ok((
+                name.trim_start_matches('/').to_string(),
+                created.with_timezone(&Utc),
+            ))
+        })
+        .collect()
+}
+
+/// Logs into `registry`, keeping the credentials in `auth_dir` rather than in the user's own
+/// config, so that only the commands [pointed to it](RunOptions::auth_dir) use them. The
+/// password is given on stdin, and hidden from the logs with the `masked` values
+pub fn docker_login(
+    registry: &str,
+    username: &str,
+    password: &str,
+    auth_dir: &Path,
+    masked: &[String],
+) -> Result<()> {
+    let runtime = binaries().runtime;
+    if runtime == Runtime::Docker {
+        inherit_docker_config(&user_docker_config(), auth_dir, registry)?;
+    }
+    let (name, value) = runtime.auth_env(auth_dir);
+    debug!(
+        "Logging into registry {} as {}",
+        registry,
+        mask(username, masked)
+    );
+    let output = docker_output(
+        docker_command()
+            .args([
+                "login",
+                registry,
+                "--username",
+                username,
+                "--password-stdin",
+            ])
+            .env(name, value),
+        password.as_bytes(),
+        masked,
+    )?;
+    if !output.status.success() {
+        let stderr = mask(&String::from_utf8_lossy(&output.stderr), masked);
+        return Err(docker_failure(
+            &output.stderr,
+            format!(
+                "Could not log into registry {}: {}",
+                registry,
+                stderr.trim()
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The docker config dir of the user running fake-ci
+fn user_docker_config() -> PathBuf {
+    match var("DOCKER_CONFIG") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(var("HOME").unwrap_or_default()).join(".docker"),
+    }
+}
+
+/// The settings of the docker config which aren't credentials, kept when [logging in](docker_login)
+const INHERITED_SETTINGS: [&str; 3] = ["credHelpers", "currentContext", "proxies"];
+
+/// Sets up `auth_dir` to stand in for the user's docker config dir `from` while a job is logged
+/// into `registry`: its credential helpers (but `registry`'s), current context & proxies are
+/// kept, and its contexts & CLI plugins linked. The `credsStore` isn't, nor are the `auths`:
+/// the other registries are only reachable through the credential helpers
+fn inherit_docker_config(from: &Path, auth_dir: &Path, registry: &str) -> Result<()> {
+    for dir in ["contexts", "cli-plugins"] {
+        if from.join(dir).is_dir() {
+            std::os::unix::fs::symlink(from.join(dir), auth_dir.join(dir))?;
+        }
+    }
+    let config: serde_json::Value = match std::fs::read(from.join("config.json")) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut inherited = serde_json::Map::new();
+    for key in INHERITED_SETTINGS {
+        if let Some(value) = config.get(key) {
+            inherited.insert(key.to_string(), value.clone());
+        }
+    }
+    // `login` would otherwise keep the job's credentials in the user's helper
+    if let Some(serde_json::Value::Object(helpers)) = inherited.get_mut("credHelpers") {
+        helpers.remove(registry);
+    }
+    std::fs::write(
+        auth_dir.join("config.json"),
+        serde_json::to_vec_pretty(&inherited)?,
+    )?;
+    Ok(())
+}
+
+/// Removes the credentials of `registry` from `auth_dir`, where [docker_login] put them
+pub fn docker_logout(registry: &str, auth_dir: &Path) -> Result<()> {
+    let (name, value) = binaries().runtime.auth_env(auth_dir);
+    let output = docker_output(
+        docker_command().args(["logout", registry]).env(name, value),
+        &[],
+        &[],
+    )?;
     if !output.status.success() {
-        return Err(anyhow!("Could not remove docker container {}", container));
+        return Err(anyhow!("Could not log out of registry {}", registry));
     }
     Ok(())
 }
 
-/// Runs the given command in the given container, then returns the output.
+/// Kills the given container, leaving it to be removed
+pub fn docker_kill(container: &str) -> Result<()> {
+    let output = docker_cmd(&["kill", container], &current_dir()?)?;
+    if !output.status.success() {
+        return Err(anyhow!("Could not kill docker container {}", container));
+    }
+    Ok(())
+}
+
+/// Runs the given command in the given running container, then returns the output.
+/// The command gets an empty stdin; see [run_in_container_with_input] to provide one.
 /// ```rust,no_run
-/// # use std::collections::HashMap;
-/// use fakeci::utils::docker::{docker_remove_container, run_from_image, run_in_container};
+/// use fakeci::utils::docker::{
+///     docker_remove_container, run_from_image, run_in_container, RunOptions, KEEP_ALIVE,
+/// };
 /// let image = "ubuntu";
 /// let cname = "fakeci-container-reuse-doctest";
 /// let commands = vec!["ls", "echo hello world"];
-/// let _ = run_from_image(image, cname, "bash", &[], &HashMap::default(), false, false);
+/// let opts = RunOptions {
+///     detach: true,
+///     ..Default::default()
+/// };
+/// let _ = run_from_image(image, cname, KEEP_ALIVE, &opts);
 /// for cmd in commands {
 ///     let o = run_in_container(cname, cmd);
 ///     assert!(o.is_ok());
@@ -195,28 +1376,158 @@ pub fn docker_remove_container(container: &str) -> Result<()> {
 /// let _ = docker_remove_container(cname);
 /// ```
 pub fn run_in_container(container: &str, command: &str) -> Result<Output> {
-    let args = &["start", "-ai", container];
+    exec_in_container(container, command, &ExecOptions::default())
+}
+
+/// Runs the given command in the given running container, with `input` as its stdin.
+/// The command is passed to `sh -c`, so stdin is left entirely to it.
+pub fn run_in_container_with_input(container: &str, command: &str, input: &[u8]) -> Result<Output> {
+    let opts = ExecOptions {
+        input: input.to_vec(),
+        ..Default::default()
+    };
+    exec_in_container(container, command, &opts)
+}
+
+#[derive(Debug)]
+/// Options used to [run a command in a container](exec_in_container)
+pub struct ExecOptions {
+    /// Absolute directory to run the command in. If None, the container's working directory
+    pub workdir: Option<String>,
+    /// The shell, with its flags, the command is passed to with `-c`. Ex: `bash -o pipefail`
+    pub shell: String,
+    /// Given to the command on its stdin
+    pub input: Vec<u8>,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        Self {
+            workdir: None,
+            shell: "sh".to_string(),
+            input: vec![],
+        }
+    }
+}
+
+/// Returns the working directory of the given running container, asking the given shell
+pub fn container_workdir(container: &str, shell: &str) -> Result<String> {
+    let command = match is_powershell(shell) {
+        true => "(Get-Location).Path",
+        false => "pwd",
+    };
+    let opts = ExecOptions {
+        shell: shell.to_string(),
+        ..Default::default()
+    };
+    let output = exec_in_container(container, command, &opts)?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Could not get the working directory of {}",
+            container
+        ));
+    }
+    let mut s = String::from_utf8_lossy(&output.stdout).to_string();
+    trim_newline(&mut s);
+    Ok(s)
+}
+
+/// Runs the given command in the given running container, with the given options
+pub fn exec_in_container(container: &str, command: &str, opts: &ExecOptions) -> Result<Output> {
+    let args = exec_args(container, command, opts)?;
     debug!("Running docker {}", &args.join(" "));
-    let mut process = Command::new("docker")
-        .args(args)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
-    let c_stdin = process.stdin.as_mut().unwrap();
-    debug!("piping \"{}\" to {}", command, container);
-    c_stdin.write_all(command.as_bytes())?;
-    Ok(process.wait_with_output()?)
+    if !opts.input.is_empty() {
+        debug!(
+            "writing {} bytes to the stdin of {}",
+            opts.input.len(),
+            container
+        );
+    }
+    docker_output(docker_command().args(args), &opts.input, &[])
+}
+
+fn exec_args(container: &str, command: &str, opts: &ExecOptions) -> Result<Vec<String>> {
+    let mut args = vec!["exec".to_string(), "-i".to_string()];
+    if let Some(dir) = &opts.workdir {
+        args.push("-w".to_string());
+        args.push(dir.to_string());
+    }
+    args.push(container.to_string());
+    args.extend(shell_command(&opts.shell, command)?);
+    Ok(args)
+}
+
+/// Mounts the docker socket of the host in a container, giving it control of the host's docker
+pub const DOCKER_SOCKET: &str = "/var/run/docker.sock:/var/run/docker.sock";
+
+#[derive(Debug, Clone)]
+/// Options used to [create a container](run_from_image)
+pub struct RunOptions {
+    /// Volumes to mount, in the `docker run --volume` format
+    pub volumes: Vec<String>,
+    /// Environment to pass to the container
+    pub env: Env,
+    /// Should the container be removed once it exits?
+    pub one_time: bool,
+    /// Should the container run in the background? Combined with a command that never exits,
+    /// such as [KEEP_ALIVE], commands can then be run in it with [run_in_container].
+    pub detach: bool,
+    /// Should the container run in privileged mode?
+    pub privileged: bool,
+    /// Should the [source dir](RunOptions::source_dir) be mounted as `/code`, and used as workdir?
+    pub mount_source: bool,
+    /// The dir to mount as `/code`. If None, the current dir
+    pub source_dir: Option<PathBuf>,
+    /// Platform of the image to pull & run. Ex: `linux/amd64`
+    pub platform: Option<String>,
+    /// Entries to add to the container's `/etc/hosts`, as `HOST:IP`
+    pub extra_hosts: Vec<String>,
+    /// Arbitrary arguments, passed as-is to `docker run`, right before the image
+    pub extra_args: Vec<String>,
+    /// Should the image be pulled first, even if it is present? If not, only the local images
+    /// can be run, which needs no network
+    pub pull: bool,
+    /// Values hidden from what fake-ci logs of the command, such as the secrets in the image's
+    /// name or the volumes
+    pub masked: Vec<String>,
+    /// The dir holding the registry credentials to pull the image with, as written by
+    /// [docker_login]. If None, those of the user running fake-ci
+    pub auth_dir: Option<PathBuf>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            volumes: vec![],
+            env: Env::new(),
+            one_time: false,
+            detach: false,
+            privileged: false,
+            mount_source: true,
+            source_dir: None,
+            platform: None,
+            extra_hosts: vec![],
+            extra_args: vec![],
+            pull: true,
+            masked: vec![],
+            auth_dir: None,
+        }
+    }
 }
 
 /// Runs the given `command` in a container created from `image`.
+/// The container gets no stdin. Fails with [FakeCiError::DaemonUnreachable] if docker's daemon
+/// can't be reached; the other failures to start the container are in the returned output.
 /// ```rust,no_run
-/// # use std::collections::HashMap;
 /// # use std::process::Output;
-/// # use fakeci::utils::docker::run_from_image;
+/// # use fakeci::utils::docker::{run_from_image, RunOptions};
 /// # let _ = pretty_env_logger::try_init();
 /// # use pretty_assertions::assert_eq;
-/// let output = run_from_image("busybox", "fake-ci-doctest","sh", &[], &HashMap::default(), true, false).expect("could not run docker :'(");
+/// let opts = RunOptions {
+///     one_time: true,
+///     ..Default::default()
+/// };
+/// let output = run_from_image("busybox", "fake-ci-doctest", "sh", &opts).expect("could not run docker :'(");
 /// assert_eq!(output.status.success(), true);
 /// assert_eq!(String::from_utf8_lossy(&output.stdout), "");
 /// ```
@@ -224,65 +1535,95 @@ pub fn run_from_image(
     image: &str,
     container_name: &str,
     command: &str,
-    volumes: &[String],
-    env: &Env,
-    one_time: bool,
-    privileged: bool,
+    opts: &RunOptions,
 ) -> Result<Output> {
-    let mut vols = vec![format!(
-        "--volume={}:{}",
-        current_dir()?
-            .to_str()
-            .expect("could not convert current dir to str"),
-        "/code"
-    )];
-    vols.extend(
-        volumes
+    let args = run_args(image, container_name, command, opts, &binaries())?;
+    debug!("Running docker {}", mask(&args.join(" "), &opts.masked));
+    debug!("waiting for docker run completion…");
+    let mut cmd = docker_command();
+    cmd.args(args).envs(&opts.env);
+    if let Some(dir) = &opts.auth_dir {
+        let (name, value) = binaries().runtime.auth_env(dir);
+        cmd.env(name, value);
+    }
+    let out = docker_output(&mut cmd, &[], &opts.masked)
+        .map_err(|e| FakeCiError::Docker(format!("could not run docker: {}", e)))?;
+    debug!("docker execution over");
+    // no container can start, on any platform: no need to look any further
+    if !out.status.success() && daemon_unreachable(&out.stderr) {
+        return Err(FakeCiError::DaemonUnreachable.into());
+    }
+    Ok(out)
+}
+
+fn run_args(
+    image: &str,
+    container_name: &str,
+    command: &str,
+    opts: &RunOptions,
+    binaries: &Binaries,
+) -> Result<Vec<String>> {
+    let runtime = binaries.runtime;
+    let mut args = vec!["run".to_string()];
+    if opts.detach {
+        args.push("--detach".to_string());
+    }
+    if opts.one_time {
+        args.push("--rm".to_string());
+    }
+    args.extend(runtime.run_flags(opts.privileged, binaries.keep_user_id));
+    args.push(format!("--name={}", container_name));
+    if opts.mount_source {
+        args.push("--workdir=/code".to_string());
+        let source_dir = match &opts.source_dir {
+            Some(dir) => dir.clone(),
+            None => current_dir()?,
+        };
+        let source = format!(
+            "{}:/code",
+            source_dir
+                .to_str()
+                .expect("could not convert the source dir to str")
+        );
+        args.push(runtime.volume_arg(&source, binaries.selinux_relabel));
+    }
+    args.extend(
+        opts.volumes
             .iter()
-            .map(|v| format!("--volume={}", v))
-            .collect::<Vec<String>>(),
+            .map(|v| runtime.volume_arg(v, binaries.selinux_relabel)),
     );
-    // yeah, we can't have a &String if the object is freed...
-    let s_run = String::from("run");
-    let cname = format!("--name={}", container_name);
-    #[allow(clippy::into_iter_on_ref)]
-    let env_args = env
-        .into_iter()
-        .flat_map(|(k, v)| {
-            let v = vec!["-e".to_string(), format!("{}={}", k, v)];
-            v.into_iter()
-        })
-        .collect::<Vec<String>>();
-    let args = {
-        let mut args: Vec<&str> = vec![&s_run, "-i"];
-        if one_time {
-            args.push("--rm");
-        }
-        if privileged {
-            args.push("--privileged");
+    for (k, v) in &opts.env {
+        args.push("-e".to_string());
+        args.push(format!("{}={}", k, v));
+    }
+    if let Some(platform) = &opts.platform {
+        args.push(format!("--platform={}", platform));
+    }
+    for host in &opts.extra_hosts {
+        match host.split_once(':') {
+            Some((name, ip)) if !name.is_empty() && !ip.is_empty() => {
+                args.push(format!("--add-host={}", host))
+            }
+            _ => {
+                return Err(FakeCiError::Config(format!(
+                    "Invalid extra host {}: expected HOST:IP",
+                    host
+                ))
+                .into())
+            }
         }
-        args.push(&cname);
-        args.push("--workdir=/code");
-        args.extend(vols.iter().map(|v| v.as_str()));
-        args.extend(env_args.iter().map(|s| s.as_str()));
-        args.push("--pull=always");
-        args.push(image);
-        args.extend(command.split_whitespace());
-        args
-    };
-    debug!("Running docker {}", &args.join(" "));
-    let mut proc = Command::new("docker")
-        .args(args)
-        .envs(env)
-        .stdin(Stdio::piped())
-        .spawn()?;
-    {
-        let stdin = proc.stdin.as_mut().unwrap();
-        debug!("writing exit to stdin…");
-        stdin.write_all(b"exit")?;
     }
-    debug!("waiting for docker run completion…");
-    let out = proc.wait_with_output()?;
-    debug!("docker execution over");
-    Ok(out)
+    args.push(match opts.pull {
+        true => "--pull=always".to_string(),
+        false => "--pull=never".to_string(),
+    });
+    args.extend(opts.extra_args.iter().cloned());
+    args.push(image.to_string());
+    // split like a shell would, so that quoted arguments stay whole
+    args.extend(
+        shlex::split(command).ok_or_else(|| {
+            FakeCiError::Config(format!("Invalid container command: {}", command))
+        })?,
+    );
+    Ok(args)
 }
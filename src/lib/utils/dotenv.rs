@@ -0,0 +1,115 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+
+use crate::Env;
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::utils::dotenv::parse_env_file;
+    use crate::Env;
+
+    #[test]
+    fn parse() {
+        let env = parse_env_file(
+            r#"# a comment
+PLAIN=value
+export EXPORTED=yes
+SPACED = around equals   # trailing comment
+DOUBLE="with # hash and \"quotes\"\nnewline"
+SINGLE='raw $HOME \n'
+EMPTY=
+URL=http://example.org/#anchor
+"#,
+        )
+        .expect("could not parse");
+        assert_eq!(
+            env,
+            Env::from(
+                [
+                    ("PLAIN", "value"),
+                    ("EXPORTED", "yes"),
+                    ("SPACED", "around equals"),
+                    ("DOUBLE", "with # hash and \"quotes\"\nnewline"),
+                    ("SINGLE", "raw $HOME \\n"),
+                    ("EMPTY", ""),
+                    ("URL", "http://example.org/#anchor"),
+                ]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_env_file("NO_EQUALS").is_err());
+        assert!(parse_env_file("BAD KEY=1").is_err());
+        assert!(parse_env_file("UNCLOSED=\"oops").is_err());
+    }
+}
+
+/// Parses the content of a `.env` file: `KEY=VALUE` lines, optionally prefixed by `export`.
+/// Blank lines & `#` comments are ignored. Values can be single-quoted (taken as-is), or
+/// double-quoted (`\"`, `\\` & `\n` are unescaped).
+pub fn parse_env_file(content: &str) -> Result<Env> {
+    let mut env = Env::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected KEY=VALUE", i + 1))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!("line {}: invalid key \"{}\"", i + 1, key));
+        }
+        let value = parse_value(value.trim()).map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+        env.insert(key.to_string(), value);
+    }
+    Ok(env)
+}
+
+fn parse_value(raw: &str) -> Result<String> {
+    if let Some(rest) = raw.strip_prefix('\'') {
+        return match rest.split_once('\'') {
+            Some((v, _)) => Ok(v.to_string()),
+            None => Err(anyhow!("unclosed single quote")),
+        };
+    }
+    if let Some(rest) = raw.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Ok(value),
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some(c) => value.push(c),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        return Err(anyhow!("unclosed double quote"));
+    }
+    // unquoted: a comment starts with a whitespace-preceded #
+    let end = raw
+        .char_indices()
+        .find(|(i, c)| *c == '#' && raw[..*i].ends_with(char::is_whitespace))
+        .map(|(i, _)| i)
+        .unwrap_or(raw.len());
+    Ok(raw[..end].trim_end().to_string())
+}
+
+/// Reads & [parses](parse_env_file) the `.env` file at `path`
+pub fn read_env_file(path: &Path) -> Result<Env> {
+    let content = read_to_string(path)
+        .map_err(|e| anyhow!("Could not read env file {}: {}", path.display(), e))?;
+    parse_env_file(&content).map_err(|e| anyhow!("Invalid env file {}: {}", path.display(), e))
+}
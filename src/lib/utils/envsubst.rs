@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::Env;
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::utils::envsubst::resolve_env;
+    use crate::Env;
+
+    fn env(vars: &[(&str, &str)]) -> Env {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn chained() {
+        let layer = env(&[
+            ("URL", "https://${HOST}:${PORT}/${ROOT}"),
+            ("HOST", "${NAME}.example.org"),
+            ("NAME", "api"),
+            ("PORT", "8443"),
+            ("ROOT", "v1"),
+        ]);
+        let resolved = resolve_env(&layer, &Env::new(), &|_| None).expect("could not resolve");
+        assert_eq!(resolved["URL"], "https://api.example.org:8443/v1");
+        assert_eq!(resolved["HOST"], "api.example.org");
+        // not references to envvars
+        let layer = env(&[("IMAGE", "${secret.REGISTRY}/app"), ("HOME", "$HOME")]);
+        assert_eq!(resolve_env(&layer, &Env::new(), &|_| None).unwrap(), layer);
+    }
+
+    #[test]
+    fn escaped() {
+        let layer = env(&[
+            ("CMD", "deploy --to $${TARGET} --from ${HOST}"),
+            ("HOST", "api"),
+            ("PRICE", "$$5"),
+        ]);
+        let resolved = resolve_env(&layer, &Env::new(), &|_| None).expect("could not resolve");
+        // left for the shell, even though it isn't defined
+        assert_eq!(resolved["CMD"], "deploy --to ${TARGET} --from api");
+        assert_eq!(resolved["PRICE"], "$$5");
+    }
+
+    #[test]
+    fn inherited() {
+        let base = env(&[("CI", "true"), ("PATH", "/usr/bin")]);
+        let layer = env(&[("IN_CI", "ci=${CI}"), ("PATH", "/opt/bin:${PATH}")]);
+        let resolved = resolve_env(&layer, &base, &|_| None).expect("could not resolve");
+        assert_eq!(
+            resolved,
+            env(&[("IN_CI", "ci=true"), ("PATH", "/opt/bin:/usr/bin")])
+        );
+        let layer = env(&[("SHELL_PATH", "${USER_SHELL}")]);
+        let resolved = resolve_env(&layer, &Env::new(), &|k| {
+            (k == "USER_SHELL").then(|| "/bin/zsh".to_string())
+        })
+        .expect("could not resolve");
+        assert_eq!(resolved["SHELL_PATH"], "/bin/zsh");
+
+        let e = resolve_env(&env(&[("A", "${NOPE}")]), &base, &|_| None)
+            .expect_err("undefined not found");
+        assert_eq!(e.to_string(), "Undefined envvar NOPE, in A: \"${NOPE}\"");
+    }
+
+    #[test]
+    fn cycle() {
+        let layer = env(&[("A", "${B}"), ("B", "x${C}"), ("C", "${A}y"), ("D", "d")]);
+        let e = resolve_env(&layer, &Env::new(), &|_| None).expect_err("cycle not found");
+        assert_eq!(
+            e.to_string(),
+            "Envvars reference each other in a cycle: A → B → C → A"
+        );
+    }
+}
+
+lazy_static! {
+    /// A reference to another envvar, in an env value. Ex: `${HOST}`. Escaped as `$${HOST}`,
+    /// it is left as `${HOST}`
+    static ref REFERENCE: Regex = Regex::new(r"\$(\$?)\{([A-Za-z_][A-Za-z0-9_]*)\}")
+        .expect("could not compile pattern");
+}
+
+/// Replaces the references to other envvars in the values of `layer`, as `${NAME}`; `$${NAME}`
+/// is an escaped one, replaced by `${NAME}` as is. A key of
+/// `layer` is resolved first, whatever their order; a key referencing itself, or not in `layer`,
+/// takes its value in `base`, the env `layer` overrides, or else from `fallback`.
+/// Errors on an undefined envvar, or keys referencing each other in a cycle.
+pub fn resolve_env(
+    layer: &Env,
+    base: &Env,
+    fallback: &dyn Fn(&str) -> Option<String>,
+) -> Result<Env> {
+    let mut resolver = Resolver {
+        layer,
+        base,
+        fallback,
+        resolved: Env::new(),
+        path: vec![],
+    };
+    // sorted, for the cycles to be reported the same way every time
+    let mut keys: Vec<&String> = layer.keys().collect();
+    keys.sort();
+    for key in keys {
+        resolver.resolve(key)?;
+    }
+    Ok(resolver.resolved)
+}
+
+struct Resolver<'a> {
+    layer: &'a Env,
+    base: &'a Env,
+    fallback: &'a dyn Fn(&str) -> Option<String>,
+    resolved: Env,
+    /// The keys being resolved, each referencing the next
+    path: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn resolve(&mut self, key: &str) -> Result<String> {
+        if let Some(v) = self.resolved.get(key) {
+            return Ok(v.to_string());
+        }
+        if let Some(i) = self.path.iter().position(|k| k == key) {
+            return Err(anyhow!(
+                "Envvars reference each other in a cycle: {} → {}",
+                self.path[i..].join(" → "),
+                key
+            ));
+        }
+        let value = &self.layer[key];
+        self.path.push(key.to_string());
+        let mut s = String::new();
+        let mut last = 0;
+        for c in REFERENCE.captures_iter(value) {
+            let m = c.get(0).unwrap();
+            s.push_str(&value[last..m.start()]);
+            last = m.end();
+            let name = &c[2];
+            if !c[1].is_empty() {
+                s.push_str(&format!("${{{}}}", name));
+                continue;
+            }
+            let v = match self.layer.contains_key(name) && name != key {
+                true => self.resolve(name)?,
+                false => self
+                    .base
+                    .get(name)
+                    .cloned()
+                    .or_else(|| (self.fallback)(name))
+                    .ok_or_else(|| {
+                        anyhow!("Undefined envvar {}, in {}: \"{}\"", name, key, value)
+                    })?,
+            };
+            s.push_str(&v);
+        }
+        s.push_str(&value[last..]);
+        self.path.pop();
+        self.resolved.insert(key.to_string(), s.clone());
+        Ok(s)
+    }
+}
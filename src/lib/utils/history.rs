@@ -0,0 +1,180 @@
+use std::fs::{create_dir_all, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+use crate::ExecutionResult;
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    use crate::notifications::{send_with_retry, Notify, Retry};
+    use crate::utils::history::{load_result, run_id, save_result};
+    use crate::{ExecutionContext, ExecutionResult, JobResult};
+
+    /// Remembers the results it was told about, as JSON
+    #[derive(Default)]
+    struct Recording(RefCell<Vec<serde_json::Value>>);
+
+    impl Notify for Recording {
+        fn send(&self, exec_res: &ExecutionResult) -> anyhow::Result<()> {
+            self.0
+                .borrow_mut()
+                .push(serde_json::to_value(exec_res).unwrap());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn saved_results() {
+        let cache = TempDir::new("fake-ci-history").expect("could not create temp dir");
+        let start = Utc.with_ymd_and_hms(2024, 1, 31, 9, 45, 0).unwrap()
+            + Duration::nanoseconds(123_456_789);
+        let res = ExecutionResult {
+            job_results: vec![JobResult {
+                name: "test".to_string(),
+                start_date: start,
+                end_date: start + Duration::seconds(42),
+                logs: vec!["--- Step 0 ---".to_string(), "FAILED".to_string()],
+                allow_failure: true,
+                ..Default::default()
+            }],
+            context: ExecutionContext {
+                repo_name: "fake-ci".to_string(),
+                branch: "main".to_string(),
+                ..Default::default()
+            },
+            start_date: start,
+            end_date: start + Duration::seconds(43),
+            ..Default::default()
+        };
+        assert_eq!(run_id(start), "20240131-094500-123456789");
+        assert_ne!(run_id(start + Duration::milliseconds(1)), run_id(start));
+        let path = save_result(cache.path(), &res).expect("could not save the result");
+        assert_eq!(
+            path,
+            cache
+                .path()
+                .join("artifacts/fake-ci/20240131-094500-123456789/result.json")
+        );
+
+        let loaded = load_result(cache.path(), "fake-ci", "20240131-094500-123456789")
+            .expect("could not load the result");
+        assert!(loaded.overall_success());
+        let recording = Recording::default();
+        send_with_retry(&recording, &loaded, &Retry::default()).expect("could not resend");
+        assert_eq!(
+            *recording.0.borrow(),
+            vec![serde_json::to_value(&res).unwrap()]
+        );
+
+        let missing = load_result(cache.path(), "fake-ci", "20240131-094500-000000000");
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn unsafe_names() {
+        let cache = TempDir::new("fake-ci-history").expect("could not create temp dir");
+        let start = Utc.with_ymd_and_hms(2024, 1, 31, 9, 45, 0).unwrap();
+        let mut res = ExecutionResult {
+            start_date: start,
+            ..Default::default()
+        };
+        res.context.repo_name = "fake-ci/internal-tests".to_string();
+        let path = save_result(cache.path(), &res).expect("could not save the result");
+        assert_eq!(
+            path,
+            cache
+                .path()
+                .join("artifacts/fake-ci%2Finternal-tests/20240131-094500-000000000/result.json")
+        );
+        let run = run_id(start);
+        assert!(load_result(cache.path(), "fake-ci/internal-tests", &run).is_ok());
+        assert!(load_result(cache.path(), "fake-ci", "internal-tests").is_err());
+        // nothing out of the cache is read
+        assert!(load_result(cache.path(), "..", &run).is_err());
+        assert!(load_result(cache.path(), "fake-ci", "../../../etc").is_err());
+        res.context.repo_name = "..".to_string();
+        assert!(save_result(cache.path(), &res).is_err());
+    }
+}
+
+/// The name of the file holding the result of a run, in its dir
+const RESULT_FILE: &str = "result.json";
+
+/// The id of a run started at `start`: the name of its dir in the cache. Ex:
+/// `20240131-094500-123456789`. The nanoseconds keep apart the runs started the same second
+pub fn run_id(start: DateTime<Utc>) -> String {
+    start.format("%Y%m%d-%H%M%S-%9f").to_string()
+}
+
+/// Is `name` the [id](run_id) of a run? Those of the runs saved before the ids had nanoseconds,
+/// such as `20240131-094500`, are too
+pub fn is_run_id(name: &str) -> bool {
+    NaiveDateTime::parse_from_str(name, "%Y%m%d-%H%M%S-%9f").is_ok()
+        || NaiveDateTime::parse_from_str(name, "%Y%m%d-%H%M%S").is_ok()
+}
+
+/// What is percent-encoded in the [dir name](repo_dir_name) of a repository
+const REPO_DIR_ESCAPED: &AsciiSet = &CONTROLS.add(b'/').add(b'\\').add(b'%');
+
+/// The name of the dir holding the runs of `repo`: a single path component, its `/`, `\` & `%`
+/// being percent-encoded. Ex: `fake-ci%2Finternal-tests`. The runs of no repository, such as
+/// those of `fake-ci run`, go to `%local`, which no repository's name is escaped to. Errors on
+/// `.` & `..`
+pub fn repo_dir_name(repo: &str) -> Result<String> {
+    match repo {
+        "" => Ok("%local".to_string()),
+        "." | ".." => Err(anyhow!("\"{}\" can't be the name of a repository", repo)),
+        _ => Ok(utf8_percent_encode(repo, REPO_DIR_ESCAPED).to_string()),
+    }
+}
+
+/// Where the runs of `repo` are kept in `cache`, with their artifacts
+pub fn repo_dir(cache: &Path, repo: &str) -> Result<PathBuf> {
+    Ok(cache.join("artifacts").join(repo_dir_name(repo)?))
+}
+
+/// Where the run `run` of `repo` is kept in `cache`, with its artifacts. Removed by the
+/// [cleanup](crate::utils::retention::cleanup). Errors if `run` isn't a [run id](run_id)
+fn run_dir(cache: &Path, repo: &str, run: &str) -> Result<PathBuf> {
+    if !is_run_id(run) {
+        return Err(anyhow!(
+            "\"{}\" is not the id of a run. Ex: 20240131-094500-123456789",
+            run
+        ));
+    }
+    Ok(repo_dir(cache, repo)?.join(run))
+}
+
+/// Saves `res` in the dir of its run in `cache`, to [load](load_result) it back later, to notify
+/// it again. Returns the file written
+pub fn save_result(cache: &Path, res: &ExecutionResult) -> Result<PathBuf> {
+    let dir = run_dir(cache, &res.context.repo_name, &run_id(res.start_date))?;
+    create_dir_all(&dir)?;
+    let path = dir.join(RESULT_FILE);
+    serde_json::to_writer_pretty(File::create(&path)?, res)?;
+    Ok(path)
+}
+
+/// Loads the result of the run `run` of `repo`, [saved](save_result) in `cache`
+pub fn load_result(cache: &Path, repo: &str, run: &str) -> Result<ExecutionResult> {
+    let path = run_dir(cache, repo, run)?.join(RESULT_FILE);
+    let file = File::open(&path).map_err(|e| {
+        anyhow!(
+            "No saved result for run {} of {} ({}): {}",
+            run,
+            repo,
+            path.display(),
+            e
+        )
+    })?;
+    Ok(serde_json::from_reader(file)?)
+}
@@ -1,23 +1,57 @@
 use std::env;
 use std::env::current_dir;
-use std::path::PathBuf;
+use std::fs::{set_permissions, OpenOptions, Permissions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
 
-use anyhow::{Error, Result};
-use log::debug;
+use anyhow::{anyhow, Error, Result};
+use log::{debug, warn};
+use tempdir::TempDir;
 
 use crate::conf::FakeCIJob;
-use crate::{FakeCIRepoConfig, Image};
+use crate::utils::commands::run_or_print;
+use crate::{Env, FakeCIRepoConfig, Image};
 
+/// Collection & archival of the files jobs produce
+pub mod artifacts;
+/// The external programs fake-ci runs: the container runtime & git
+pub mod binaries;
+/// Colors of the terminal output, and when to use them
+pub mod color;
+/// The commands run for the pipelines, or only printed
+pub mod commands;
 /// Utility functions for docker, mostly docker commands
 pub mod docker;
+/// Parsing of `.env` files
+pub mod dotenv;
+/// References to other envvars in env values, as `${NAME}`
+pub mod envsubst;
 /// Utility functions for git. Mostly OS interface.
 pub mod git;
+/// The results of the past runs, kept in the cache dir to be notified again
+pub mod history;
+/// Checks the environment fake-ci runs in
+pub mod preflight;
+/// Pruning of old runs from the cache dir
+pub mod retention;
+/// The differences between the container runtimes
+pub mod runtime;
+/// Tracing spans around the pipeline, its jobs & their steps, with the `tracing` feature
+pub(crate) mod spans;
+/// Abstraction over version control backends
+pub mod vcs;
 
 #[cfg(test)]
-pub mod tests {
+pub(crate) mod tests {
     use std::env::{current_dir, set_current_dir};
     use std::fs::File;
     use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
     use std::path::{Path, PathBuf};
     use std::sync::{Arc, Mutex};
 
@@ -25,12 +59,16 @@ pub mod tests {
     use lazy_static::lazy_static;
     use log::debug;
 
-    use crate::FakeCIRepoConfig;
+    use crate::utils::{
+        get_job_image_or_default, is_powershell, private_dir, shell_command, write_private,
+    };
+    use crate::{FakeCIRepoConfig, Image};
 
     lazy_static! {
         static ref WITH_DIR_MUTEX: Arc<Mutex<u8>> = Arc::new(Mutex::new(0u8));
     }
 
+    #[allow(dead_code)]
     pub fn ser_yaml(conf: &FakeCIRepoConfig) -> Result<String> {
         Ok(serde_yaml::to_string(conf)?)
     }
@@ -48,23 +86,109 @@ pub mod tests {
         Ok(s)
     }
 
+    #[test]
+    fn job_image() {
+        let c = deser_yaml(&get_sample_resource_file("basic_config.yml").unwrap()).unwrap();
+        let j0 = c.pipeline.first().unwrap();
+        assert_eq!(
+            get_job_image_or_default(j0, &c).unwrap(),
+            &Image::Existing("ubuntu".to_string())
+        );
+    }
+
+    #[test]
+    fn default_image() {
+        let c = deser_yaml(&get_sample_resource_file("job_container_reuse.yml").unwrap()).unwrap();
+        let j0 = c.pipeline.first().unwrap();
+        assert!(j0.image.is_none());
+        assert_eq!(
+            get_job_image_or_default(j0, &c).unwrap(),
+            &Image::Existing("busybox".to_string())
+        );
+        // a job that isn't part of the pipeline still gets the default image
+        let other = deser_yaml(&get_sample_resource_file("secrets.yml").unwrap()).unwrap();
+        let j = other.pipeline.first().unwrap();
+        assert!(!c.pipeline.contains(j));
+        assert_eq!(
+            get_job_image_or_default(j, &c).unwrap(),
+            &Image::Existing("busybox".to_string())
+        );
+        // no default image at all
+        let no_default =
+            deser_yaml(&get_sample_resource_file("basic_config.yml").unwrap()).unwrap();
+        assert!(get_job_image_or_default(j, &no_default).is_err());
+    }
+
+    #[test]
+    fn shell_commands() {
+        assert_eq!(
+            shell_command("bash -o pipefail", "echo $HOME").unwrap(),
+            vec!["bash", "-o", "pipefail", "-c", "echo $HOME"]
+        );
+        assert_eq!(
+            shell_command("powershell", "Get-ChildItem | Select-Object Name").unwrap(),
+            vec![
+                "powershell",
+                "-NoProfile",
+                "-NonInteractive",
+                "-Command",
+                "Get-ChildItem | Select-Object Name"
+            ]
+        );
+        assert_eq!(
+            shell_command("pwsh -ExecutionPolicy Bypass", "ls").unwrap(),
+            vec![
+                "pwsh",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-NoProfile",
+                "-NonInteractive",
+                "-Command",
+                "ls"
+            ]
+        );
+        assert!(is_powershell("/opt/microsoft/powershell/7/pwsh"));
+        assert!(is_powershell(
+            "C:\\Windows\\System32\\WindowsPowerShell\\v1.0\\PowerShell.exe -NoLogo"
+        ));
+        assert!(!is_powershell("sh"));
+        assert!(shell_command(" ", "ls").is_err());
+    }
+
+    #[test]
+    fn private_files() {
+        let dir = private_dir("fake-ci-private").expect("could not create temp dir");
+        let path = dir.path().join("secret");
+        write_private(&path, "hunter2").unwrap();
+        let mode = |p: &Path| p.metadata().unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(dir.path()), 0o700);
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hunter2");
+        assert!(write_private(&path, "again").is_err());
+    }
+
     pub fn with_dir<F>(path: &Path, f: F)
     where
         F: FnOnce(),
     {
         let arc = Arc::clone(&WITH_DIR_MUTEX);
-        let _lock = arc.lock().expect("could not aquire lock");
+        let _lock = arc.lock().unwrap_or_else(|e| e.into_inner());
         let old_path = current_dir().expect("could not get current dir");
         debug!("path: {}", old_path.display());
         if path != old_path {
-            let _ = set_current_dir(&path);
+            let _ = set_current_dir(path);
             debug!("new path: {}", path.display());
         }
-        f();
-        if path != old_path {
-            let _ = set_current_dir(&old_path);
-            debug!("new path: {}", old_path.display());
+        // restores the old path, even if f panics
+        struct Restore(PathBuf);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let _ = set_current_dir(&self.0);
+                debug!("new path: {}", self.0.display());
+            }
         }
+        let _restore = Restore(old_path);
+        f();
     }
 }
 
@@ -90,17 +214,55 @@ pub fn get_job_image_or_default<'a>(
     job: &'a FakeCIJob,
     config: &'a FakeCIRepoConfig,
 ) -> Result<&'a Image> {
-    for j in &config.pipeline {
-        if j == job {
-            if j.image.is_some() {
-                debug!("found configured job image: {:?}", j.image);
-                return Ok(j.image.as_ref().unwrap());
-            } else if config.default.is_some() && config.default.as_ref().unwrap().image.is_some() {
-                return Ok(config.default.as_ref().unwrap().image.as_ref().unwrap());
-            }
-        }
+    if let Some(image) = &job.image {
+        debug!("found configured job image: {:?}", image);
+        return Ok(image);
+    }
+    match config.default.as_ref().and_then(|d| d.image.as_ref()) {
+        Some(image) => Ok(image),
+        None => Err(Error::msg(format!(
+            "Job \"{}\" has no image, and no default image is configured",
+            job.name
+        ))),
     }
-    Err(Error::msg("Could not find the given job in the config"))
+}
+
+/// Errors if `p` is absolute, or could point outside of the directory it is relative to
+pub fn check_relative(p: &Path) -> Result<()> {
+    match p
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        true => Ok(()),
+        false => Err(anyhow!(
+            "{} must be relative, and stay inside the repository",
+            p.display()
+        )),
+    }
+}
+
+/// Does the existing `path` stay inside `root`, once their symlinks are resolved? A symlink
+/// committed, or created by a job, could otherwise lead fake-ci to the host's files
+pub fn resolves_inside(root: &Path, path: &Path) -> Result<bool> {
+    Ok(path.canonicalize()?.starts_with(root.canonicalize()?))
+}
+
+/// Creates a temporary dir only its owner can read, for the files holding secrets
+pub fn private_dir(prefix: &str) -> Result<TempDir> {
+    let dir = TempDir::new(prefix)?;
+    set_permissions(dir.path(), Permissions::from_mode(0o700))?;
+    Ok(dir)
+}
+
+/// Writes `contents` to a new file only its owner can read & write
+pub fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
 }
 
 /// Returns the cache dir in use
@@ -114,3 +276,98 @@ pub fn cache_dir() -> PathBuf {
     };
     path.join("fake-ci")
 }
+
+/// Is `shell` PowerShell? Ex: `powershell`, `pwsh -NoLogo`, `/opt/microsoft/powershell/7/pwsh`
+pub fn is_powershell(shell: &str) -> bool {
+    let program = shell.split_whitespace().next().unwrap_or_default();
+    let name = program
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    matches!(name.trim_end_matches(".exe"), "powershell" | "pwsh")
+}
+
+/// Builds the command line running `command` with `shell`, which can have flags, ex:
+/// `bash -o pipefail`. POSIX shells get the command with `-c`, [PowerShell](is_powershell) with
+/// `-NoProfile -NonInteractive -Command`.
+pub fn shell_command(shell: &str, command: &str) -> Result<Vec<String>> {
+    let mut args: Vec<String> = shell.split_whitespace().map(|s| s.to_string()).collect();
+    if args.is_empty() {
+        return Err(anyhow!("The shell is empty"));
+    }
+    match is_powershell(shell) {
+        true => args.extend(
+            ["-NoProfile", "-NonInteractive", "-Command"]
+                .iter()
+                .map(|s| s.to_string()),
+        ),
+        false => args.push("-c".to_string()),
+    }
+    args.push(command.to_string());
+    Ok(args)
+}
+
+/// Runs `command` directly on the host with the [shell](shell_command), in `workdir` or the
+/// current directory. `env` is added to fake-ci's own environment. The command is killed, with
+/// the processes it started, once `stop` returns true.
+pub fn run_on_host(
+    command: &str,
+    env: &Env,
+    workdir: Option<&Path>,
+    shell: &str,
+    stop: impl Fn() -> bool,
+) -> Result<Output> {
+    let args = shell_command(shell, command)?;
+    debug!("Running {:?} on the host", args);
+    let mut cmd = Command::new(&args[0]);
+    cmd.args(&args[1..]).envs(env);
+    if let Some(dir) = workdir {
+        cmd.current_dir(dir);
+    }
+    run_or_print(&mut cmd, &[], |cmd| output_until(cmd, stop))
+}
+
+/// Runs `cmd` like [Command::output], but kills it once `stop` returns true. It runs in its own
+/// process group, killed as a whole: the processes it started would otherwise keep running,
+/// and keep its output open
+fn output_until(cmd: &mut Command, stop: impl Fn() -> bool) -> Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    // pipes are drained in the background, so a chatty child can't block on a full pipe
+    fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buf = vec![];
+            if let Some(mut p) = pipe {
+                let _ = p.read_to_end(&mut buf);
+            }
+            buf
+        })
+    }
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if stop() {
+            debug!("Killing the process group {}", child.id());
+            let group = format!("-{}", child.id());
+            if let Err(e) = Command::new("kill").args(["-KILL", "--", &group]).status() {
+                warn!("Could not kill the process group {}: {}", child.id(), e);
+                let _ = child.kill();
+            }
+            break child.wait()?;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
@@ -0,0 +1,162 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{create_dir_all, remove_file, File};
+use std::io;
+use std::path::Path;
+use std::process::Output;
+
+use anyhow::Result;
+use log::debug;
+
+use crate::utils::binaries::{docker_command, git_command};
+use crate::utils::cache_dir;
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    use crate::utils::preflight::{all_passed, check_cache_dir, check_output, CheckResult};
+
+    fn output(code: i32, stdout: &str, stderr: &str) -> io::Result<Output> {
+        Ok(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+
+    #[test]
+    fn check_results() {
+        let ok = check_output("git", output(0, "git version 2.34.1\n", ""), "install git");
+        assert!(ok.success);
+        assert_eq!(ok.message, "git version 2.34.1");
+
+        let failed = check_output(
+            "docker",
+            output(1, "", "Cannot connect to the Docker daemon\n"),
+            "start the docker daemon",
+        );
+        assert!(!failed.success);
+        assert!(failed
+            .message
+            .contains("Cannot connect to the Docker daemon"));
+        assert!(failed.message.contains("start the docker daemon"));
+
+        let missing = check_output(
+            "docker",
+            Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+            "install docker",
+        );
+        assert!(!missing.success);
+        assert!(missing.message.contains("install docker"));
+
+        assert!(all_passed(&[ok]));
+        assert!(!all_passed(&[
+            CheckResult {
+                name: "git".to_string(),
+                success: true,
+                message: "".to_string(),
+            },
+            failed,
+        ]));
+    }
+
+    #[test]
+    fn cache_dir_writable() {
+        let dir = TempDir::new("fake-ci-preflight").expect("could not create temp dir");
+        assert!(check_cache_dir(&dir.path().join("cache")).success);
+    }
+}
+
+#[derive(Debug)]
+/// The outcome of a single [preflight] check
+pub struct CheckResult {
+    /// What was checked
+    pub name: String,
+    /// Did the check pass?
+    pub success: bool,
+    /// Details on the check; on failure, tells what to do about it
+    pub message: String,
+}
+
+impl Display for CheckResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mark = match self.success {
+            true => "✓",
+            false => "✗",
+        };
+        write!(f, "{} {}: {}", mark, self.name, self.message)
+    }
+}
+
+/// Returns true if all the given checks passed
+pub fn all_passed(results: &[CheckResult]) -> bool {
+    results.iter().all(|r| r.success)
+}
+
+fn check_output(name: &str, output: io::Result<Output>, hint: &str) -> CheckResult {
+    let (success, message) = match output {
+        Ok(o) if o.status.success() => (
+            true,
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        Ok(o) => (
+            false,
+            format!("{} ({})", String::from_utf8_lossy(&o.stderr).trim(), hint),
+        ),
+        Err(e) => (false, format!("{} ({})", e, hint)),
+    };
+    CheckResult {
+        name: name.to_string(),
+        success,
+        message,
+    }
+}
+
+fn check_cache_dir(cache: &Path) -> CheckResult {
+    let probe = cache.join(".fake-ci-preflight");
+    let res = create_dir_all(cache)
+        .and_then(|_| File::create(&probe))
+        .and_then(|_| remove_file(&probe));
+    CheckResult {
+        name: "cache dir".to_string(),
+        success: res.is_ok(),
+        message: match res {
+            Ok(_) => format!("{} is writable", cache.display()),
+            Err(e) => format!(
+                "could not write to {}: {} (check its permissions, or set XDG_CACHE_HOME)",
+                cache.display(),
+                e
+            ),
+        },
+    }
+}
+
+/// Checks that everything fake-ci needs is available: `git`, a reachable docker daemon, and a
+/// writable [cache dir](cache_dir).
+pub fn preflight() -> Result<Vec<CheckResult>> {
+    debug!("Running preflight checks");
+    Ok(vec![
+        check_output(
+            "git",
+            git_command().arg("--version").output(),
+            "is git installed & in the PATH?",
+        ),
+        check_output(
+            "docker",
+            docker_command()
+                .args(["version", "--format", "{{.Server.Version}}"])
+                .output(),
+            "is docker installed, and is its daemon running & reachable?",
+        ),
+        check_cache_dir(&cache_dir()),
+    ])
+}
@@ -0,0 +1,309 @@
+use std::cmp::Reverse;
+use std::fs::{read_dir, remove_dir_all};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::docker::{docker_remove_container, fakeci_containers};
+use crate::utils::history::is_run_id;
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write, File};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::utils::history::{repo_dir, repo_dir_name};
+    use crate::utils::retention::{cleanup, expired_containers, Retention};
+
+    const DAY: u64 = 24 * 3600;
+
+    /// Creates the run dir `repo/run` in `cache`'s artifacts, last modified `days` ago
+    fn run(cache: &Path, repo: &str, run: &str, days: u64, now: SystemTime) {
+        let dir = cache.join("artifacts").join(repo).join(run);
+        create_dir_all(&dir).expect("could not create run dir");
+        write(dir.join("app"), "binary").expect("could not write artifact");
+        File::open(&dir)
+            .and_then(|f| f.set_modified(now - Duration::from_secs(days * DAY)))
+            .expect("could not set mtime");
+    }
+
+    fn sample(now: SystemTime) -> TempDir {
+        let cache = TempDir::new("fake-ci-retention").expect("could not create temp dir");
+        for (repo, name, days) in [
+            ("fake-ci", "20211101-120000", 30),
+            ("fake-ci", "20211120-120000", 10),
+            ("fake-ci", "20211127-120000", 3),
+            ("fake-ci", "20211129-120000", 1),
+            ("other", "20211015-120000", 45),
+        ] {
+            run(cache.path(), repo, name, days, now);
+        }
+        // the branch refs caches
+        write(cache.path().join("fake-ci.yml"), "main: abc123").unwrap();
+        write(cache.path().join("fake-ci.built.yml"), "main: abc123").unwrap();
+        cache
+    }
+
+    fn remaining(cache: &Path) -> Vec<String> {
+        let mut runs = vec![];
+        for repo in ["fake-ci", "other"] {
+            let dir = cache.join("artifacts").join(repo);
+            if let Ok(entries) = dir.read_dir() {
+                for e in entries {
+                    let name = e.unwrap().file_name().to_string_lossy().to_string();
+                    runs.push(format!("{}/{}", repo, name));
+                }
+            }
+        }
+        runs.sort();
+        runs
+    }
+
+    #[test]
+    fn max_age() {
+        let now = SystemTime::now();
+        let cache = sample(now);
+        let retention = Retention {
+            max_age_days: Some(7),
+            ..Default::default()
+        };
+        let removed = cleanup(cache.path(), &retention, now).expect("could not clean up");
+        assert_eq!(removed.len(), 3);
+        assert_eq!(
+            remaining(cache.path()),
+            vec!["fake-ci/20211127-120000", "fake-ci/20211129-120000"]
+        );
+        assert!(cache.path().join("fake-ci.yml").is_file());
+        assert!(cache.path().join("fake-ci.built.yml").is_file());
+    }
+
+    #[test]
+    fn max_runs() {
+        let now = SystemTime::now();
+        let cache = sample(now);
+        let retention = Retention {
+            max_runs_per_repo: Some(2),
+            ..Default::default()
+        };
+        cleanup(cache.path(), &retention, now).expect("could not clean up");
+        assert_eq!(
+            remaining(cache.path()),
+            vec![
+                "fake-ci/20211127-120000",
+                "fake-ci/20211129-120000",
+                "other/20211015-120000"
+            ]
+        );
+
+        // both limits apply
+        let cache = sample(now);
+        let retention = Retention {
+            max_age_days: Some(2),
+            max_runs_per_repo: Some(3),
+            ..Default::default()
+        };
+        cleanup(cache.path(), &retention, now).expect("could not clean up");
+        assert_eq!(remaining(cache.path()), vec!["fake-ci/20211129-120000"]);
+    }
+
+    #[test]
+    fn repo_names_with_slashes() {
+        let now = SystemTime::now();
+        let cache = TempDir::new("fake-ci-retention").expect("could not create temp dir");
+        assert_eq!(
+            repo_dir_name("fake-ci/internal-tests").unwrap(),
+            "fake-ci%2Finternal-tests"
+        );
+        assert!(repo_dir_name("..").is_err());
+        for repo in ["fake-ci/internal-tests", "fake-ci/other"] {
+            let dir = repo_dir(cache.path(), repo).unwrap();
+            let dir = dir.strip_prefix(cache.path().join("artifacts")).unwrap();
+            for (name, days) in [("20211127-120000", 3), ("20211129-120000", 1)] {
+                run(cache.path(), dir.to_str().unwrap(), name, days, now);
+            }
+        }
+        // a repository of the layout before the names were escaped
+        run(
+            cache.path(),
+            "fake-ci/internal-tests",
+            "20211129-120000",
+            1,
+            now,
+        );
+        let retention = Retention {
+            max_runs_per_repo: Some(1),
+            ..Default::default()
+        };
+        let mut removed = cleanup(cache.path(), &retention, now).expect("could not clean up");
+        removed.sort();
+        let artifacts = cache.path().join("artifacts");
+        assert_eq!(
+            removed,
+            vec![
+                artifacts.join("fake-ci%2Finternal-tests/20211127-120000"),
+                artifacts.join("fake-ci%2Fother/20211127-120000"),
+            ]
+        );
+        // not taken for a run
+        assert!(artifacts.join("fake-ci/internal-tests").is_dir());
+    }
+
+    #[test]
+    fn old_containers() {
+        let now = Utc.with_ymd_and_hms(2021, 11, 30, 12, 0, 0).unwrap();
+        let containers = vec![
+            (
+                "fake-ci-build-a1b2".to_string(),
+                now - chrono::Duration::days(10),
+            ),
+            (
+                "fake-ci-test-c3d4".to_string(),
+                now - chrono::Duration::days(2),
+            ),
+            (
+                "fake-ci-lint-e5f6".to_string(),
+                now - chrono::Duration::hours(1),
+            ),
+        ];
+        let retention = Retention {
+            kept_containers_days: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            expired_containers(containers.clone(), &retention, now),
+            vec!["fake-ci-build-a1b2"]
+        );
+        // kept forever by default
+        assert!(expired_containers(containers, &Retention::default(), now).is_empty());
+    }
+
+    #[test]
+    fn nothing_to_clean() {
+        let now = SystemTime::now();
+        let cache = sample(now);
+        let removed = cleanup(cache.path(), &Retention::default(), now).unwrap();
+        assert!(removed.is_empty());
+        assert_eq!(remaining(cache.path()).len(), 5);
+        let empty = TempDir::new("fake-ci-retention").unwrap();
+        assert!(cleanup(empty.path(), &Retention::default(), now)
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+/// How long the runs of each repository are kept in the cache dir. Without any limit, they are
+/// kept forever.
+pub struct Retention {
+    /// Remove the runs older than this many days
+    pub max_age_days: Option<u64>,
+    /// Only keep this many runs of each repository, the most recent ones
+    pub max_runs_per_repo: Option<usize>,
+    /// Remove the jobs' containers left behind, [kept on
+    /// failure](crate::conf::FakeCIJob::keep_on_failure) or by a crash, once this many days old
+    pub kept_containers_days: Option<u64>,
+}
+
+/// The names of the `containers` older than `retention` keeps them, as of `now`
+fn expired_containers(
+    containers: Vec<(String, DateTime<Utc>)>,
+    retention: &Retention,
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let Some(days) = retention.kept_containers_days else {
+        return vec![];
+    };
+    let max_age = chrono::Duration::days(days as i64);
+    containers
+        .into_iter()
+        .filter(|(_, created)| now - *created > max_age)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Removes the containers of fake-ci's jobs older than `retention` keeps them, as of `now`: a
+/// job running for longer than that would be removed too. Returns the removed containers
+pub fn reap_containers(retention: &Retention, now: DateTime<Utc>) -> Result<Vec<String>> {
+    if retention.kept_containers_days.is_none() {
+        return Ok(vec![]);
+    }
+    let mut removed = vec![];
+    for name in expired_containers(fakeci_containers()?, retention, now) {
+        debug!("Removing container {}", name);
+        match docker_remove_container(&name) {
+            Ok(()) => removed.push(name),
+            Err(e) => warn!("Could not remove container {}: {}", name, e),
+        }
+    }
+    if !removed.is_empty() {
+        info!("Removed {} old container(s)", removed.len());
+    }
+    Ok(removed)
+}
+
+/// The run dirs of a repository, the most recent first. The dirs not named like a [run
+/// id](crate::utils::history::run_id) are left out: they aren't runs
+fn runs(repo_dir: &Path) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut runs = vec![];
+    for entry in read_dir(repo_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        match entry.file_name().to_str().is_some_and(is_run_id) {
+            true => runs.push((entry.path(), entry.metadata()?.modified()?)),
+            false => warn!("Not cleaning up {}: not a run", entry.path().display()),
+        }
+    }
+    runs.sort_by_key(|r| Reverse(r.1));
+    Ok(runs)
+}
+
+/// Removes the runs of `cache`'s artifacts dir not kept by `retention`, as of `now`. Each of its
+/// dirs holds the runs of a [repository](crate::utils::history::repo_dir_name).
+/// Only the run dirs are removed; the branch refs caches are left alone.
+/// Returns the removed dirs.
+pub fn cleanup(cache: &Path, retention: &Retention, now: SystemTime) -> Result<Vec<PathBuf>> {
+    let artifacts = cache.join("artifacts");
+    if !artifacts.is_dir() {
+        return Ok(vec![]);
+    }
+    let max_age = retention
+        .max_age_days
+        .map(|d| Duration::from_secs(d * 24 * 3600));
+    let mut removed = vec![];
+    for repo in read_dir(&artifacts)? {
+        let repo = repo?;
+        if !repo.file_type()?.is_dir() {
+            continue;
+        }
+        for (i, (run, modified)) in runs(&repo.path())?.into_iter().enumerate() {
+            let too_many = retention.max_runs_per_repo.is_some_and(|max| i >= max);
+            let too_old =
+                max_age.is_some_and(|max| now.duration_since(modified).is_ok_and(|age| age > max));
+            if too_many || too_old {
+                debug!("Removing run {}", run.display());
+                remove_dir_all(&run)?;
+                removed.push(run);
+            }
+        }
+    }
+    if !removed.is_empty() {
+        info!(
+            "Removed {} old run(s) from {}",
+            removed.len(),
+            cache.display()
+        );
+    }
+    Ok(removed)
+}
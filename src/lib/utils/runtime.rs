@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use std::path::Path;
+
+    use crate::utils::runtime::Runtime;
+
+    #[test]
+    fn volumes() {
+        for (spec, relabeled) in [
+            ("/srv/cache:/cache", "/srv/cache:/cache:Z"),
+            ("/srv/cache:/cache:ro", "/srv/cache:/cache:ro,Z"),
+            (
+                "fake-ci-target:/code/target",
+                "fake-ci-target:/code/target:Z",
+            ),
+            // anonymous volumes have nothing to relabel
+            ("/cache", "/cache"),
+        ] {
+            assert_eq!(
+                Runtime::Podman.volume_arg(spec, true),
+                format!("--volume={}", relabeled)
+            );
+            assert_eq!(
+                Runtime::Podman.volume_arg(spec, false),
+                format!("--volume={}", spec)
+            );
+            assert_eq!(
+                Runtime::Docker.volume_arg(spec, true),
+                format!("--volume={}", spec)
+            );
+        }
+    }
+
+    #[test]
+    fn run_flags() {
+        assert!(Runtime::Docker.run_flags(false, true).is_empty());
+        assert_eq!(Runtime::Docker.run_flags(true, true), vec!["--privileged"]);
+        assert!(Runtime::Podman.run_flags(false, false).is_empty());
+        assert_eq!(
+            Runtime::Podman.run_flags(true, true),
+            vec!["--privileged", "--userns=keep-id"]
+        );
+    }
+
+    #[test]
+    fn auth_env() {
+        let dir = Path::new("/tmp/auth");
+        assert_eq!(
+            Runtime::Docker.auth_env(dir),
+            ("DOCKER_CONFIG", dir.to_path_buf())
+        );
+        assert_eq!(
+            Runtime::Podman.auth_env(dir),
+            ("REGISTRY_AUTH_FILE", dir.join("auth.json"))
+        );
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// The container runtime running the jobs. Podman is CLI-compatible with docker, with a few
+/// differences in its defaults, handled here
+pub enum Runtime {
+    #[default]
+    /// `docker`
+    Docker,
+    /// `podman`
+    Podman,
+}
+
+impl Runtime {
+    /// The name of the runtime's binary
+    pub fn program(&self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+        }
+    }
+
+    /// The `run` argument mounting the volume `spec`, in the `--volume` format. With podman,
+    /// `relabel` adds the `Z` option, so that SELinux lets the container access the volume.
+    pub fn volume_arg(&self, spec: &str, relabel: bool) -> String {
+        let parts = spec.split(':').count();
+        match self {
+            Runtime::Podman if relabel && parts == 2 => format!("--volume={}:Z", spec),
+            Runtime::Podman if relabel && parts > 2 => format!("--volume={},Z", spec),
+            _ => format!("--volume={}", spec),
+        }
+    }
+
+    /// The `run` arguments setting the container's privileges & user. With podman, `keep_user_id`
+    /// maps the user running fake-ci into the container, so that the files the jobs create in
+    /// mounted volumes belong to it, and not to a sub-uid of a rootless user namespace.
+    pub fn run_flags(&self, privileged: bool, keep_user_id: bool) -> Vec<String> {
+        let mut flags = vec![];
+        if privileged {
+            flags.push("--privileged".to_string());
+        }
+        if *self == Runtime::Podman && keep_user_id {
+            flags.push("--userns=keep-id".to_string());
+        }
+        flags
+    }
+
+    /// The envvar pointing the runtime to where it keeps its registry credentials, and its value
+    /// to keep them in `dir`: `login` then writes there, rather than in the user's own config
+    pub fn auth_env(&self, dir: &Path) -> (&'static str, PathBuf) {
+        match self {
+            Runtime::Docker => ("DOCKER_CONFIG", dir.to_path_buf()),
+            Runtime::Podman => ("REGISTRY_AUTH_FILE", dir.join("auth.json")),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::collections::HashMap;
+    use std::fmt::Debug;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, ThreadId};
+
+    use anyhow::anyhow;
+    use pretty_assertions::assert_eq;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::utils::git::Commit;
+    use crate::utils::vcs::Vcs;
+    use crate::{execute_from_reader, LaunchOptions};
+
+    /// A span: its name, the index of its parent, and its fields
+    type Recorded = (&'static str, Option<usize>, HashMap<String, String>);
+
+    /// The spans each thread entered, innermost last
+    type Entered = HashMap<ThreadId, Vec<usize>>;
+
+    /// Records the spans created, and which one each thread entered
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<(Vec<Recorded>, Entered)>>);
+
+    struct Fields<'a>(&'a mut HashMap<String, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+            let mut r = self.0.lock().unwrap();
+            let parent = match attrs.parent() {
+                Some(id) => Some(id.into_u64() as usize - 1),
+                None if attrs.is_contextual() => {
+                    r.1.get(&thread::current().id())
+                        .and_then(|e| e.last().copied())
+                }
+                None => None,
+            };
+            let mut fields = HashMap::new();
+            attrs.record(&mut Fields(&mut fields));
+            r.0.push((attrs.metadata().name(), parent, fields));
+            Id::from_u64(r.0.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut r = self.0.lock().unwrap();
+            values.record(&mut Fields(&mut r.0[span.into_u64() as usize - 1].2));
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, span: &Id) {
+            let mut r = self.0.lock().unwrap();
+            r.1.entry(thread::current().id())
+                .or_default()
+                .push(span.into_u64() as usize - 1);
+        }
+
+        fn exit(&self, _span: &Id) {
+            let mut r = self.0.lock().unwrap();
+            r.1.get_mut(&thread::current().id()).unwrap().pop();
+        }
+    }
+
+    struct HeadOnlyVcs;
+
+    impl Vcs for HeadOnlyVcs {
+        fn list_refs(&self, _uri: &str) -> anyhow::Result<HashMap<String, String>> {
+            Ok(HashMap::new())
+        }
+
+        fn default_branch(&self, _uri: &str) -> anyhow::Result<String> {
+            Ok("main".to_string())
+        }
+
+        fn clone_ref(&self, _uri: &str, _reference: &str, _to: &Path) -> anyhow::Result<()> {
+            Err(anyhow!("HeadOnlyVcs can't clone"))
+        }
+
+        fn head_commit(&self, _repo: &Path) -> anyhow::Result<Commit> {
+            Ok(Commit {
+                hash: "abc123".to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let recorder = Recorder::default();
+        let conf = "stages: [build, test]
+pipeline:
+  - name: build
+    stage: build
+    runner: host
+    steps:
+      - name: compile
+        exec: [\"true\"]
+  - name: test
+    stage: test
+    runner: host
+    steps:
+      - name: unit
+        exec: [\"false\"]
+  - name: lint
+    stage: test
+    runner: host
+    steps:
+      - name: clippy
+        exec: [\"true\"]";
+        let opts = LaunchOptions {
+            repo_name: "spans".to_string(),
+            branch: "main".to_string(),
+            ..Default::default()
+        };
+        tracing::subscriber::with_default(recorder.clone(), || {
+            execute_from_reader(conf.as_bytes(), "stdin", &opts, &HeadOnlyVcs, &[])
+                .expect("could not run the pipeline");
+        });
+        let (spans, entered) = &*recorder.0.lock().unwrap();
+        assert!(entered.values().all(Vec::is_empty));
+        let label = |fields: &HashMap<String, String>| {
+            fields.get("name").or_else(|| fields.get("repo")).cloned()
+        };
+        // the jobs of the test stage run in their own threads, their spans in any order
+        let mut tree: Vec<(&str, Option<String>, String, &str)> = spans
+            .iter()
+            .map(|(name, parent, fields)| {
+                assert!(
+                    fields.contains_key("duration_ms"),
+                    "{} has no duration",
+                    name
+                );
+                (
+                    *name,
+                    parent.and_then(|p| label(&spans[p].2)),
+                    label(fields).unwrap(),
+                    fields["status"].as_str(),
+                )
+            })
+            .collect();
+        tree.sort();
+        let parent = |p: &str| Some(p.to_string());
+        assert_eq!(
+            tree,
+            vec![
+                ("job", parent("spans"), "build".to_string(), "success"),
+                ("job", parent("spans"), "lint".to_string(), "success"),
+                ("job", parent("spans"), "test".to_string(), "failure"),
+                ("pipeline", None, "spans".to_string(), "failure"),
+                ("step", parent("build"), "compile".to_string(), "success"),
+                ("step", parent("lint"), "clippy".to_string(), "success"),
+                ("step", parent("test"), "unit".to_string(), "failure"),
+            ]
+        );
+        assert_eq!(spans[0].2["branch"], "main");
+        assert_eq!(spans[0].2["commit"], "abc123");
+    }
+}
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use std::time::Instant;
+
+    use tracing::field::Empty;
+    use tracing::info_span;
+    use tracing::span::Entered;
+    use tracing::{dispatcher, Dispatch};
+
+    use crate::ExecutionContext;
+
+    /// A [tracing] span, which records its status & duration once [ended](Span::end)
+    pub(crate) struct Span {
+        span: tracing::Span,
+        start: Instant,
+    }
+
+    impl Span {
+        fn new(span: tracing::Span) -> Self {
+            Span {
+                span,
+                start: Instant::now(),
+            }
+        }
+
+        pub(crate) fn pipeline(ctx: &ExecutionContext) -> Self {
+            Span::new(info_span!(
+                "pipeline",
+                repo = %ctx.repo_name,
+                branch = %ctx.branch,
+                commit = %ctx.commit.hash,
+                status = Empty,
+                duration_ms = Empty,
+            ))
+        }
+
+        pub(crate) fn job(name: &str) -> Self {
+            Span::new(info_span!("job", name, status = Empty, duration_ms = Empty))
+        }
+
+        pub(crate) fn step(name: &str) -> Self {
+            Span::new(info_span!(
+                "step",
+                name,
+                status = Empty,
+                duration_ms = Empty
+            ))
+        }
+
+        /// Makes this span the parent of the spans created until the guard is dropped
+        pub(crate) fn enter(&self) -> Entered<'_> {
+            self.span.enter()
+        }
+
+        /// Records how it went, and how long it took since the span was created
+        pub(crate) fn end(&self, status: &str) {
+            self.span.record("status", status);
+            self.span
+                .record("duration_ms", self.start.elapsed().as_millis() as u64);
+        }
+
+        /// This span, and the subscriber of the current thread, to carry over to the threads
+        /// it starts: both are per-thread
+        pub(crate) fn context(&self) -> Context {
+            Context {
+                dispatch: dispatcher::get_default(Dispatch::clone),
+                span: self.span.clone(),
+            }
+        }
+    }
+
+    /// A span to run the spans of another thread in, as their parent
+    pub(crate) struct Context {
+        dispatch: Dispatch,
+        span: tracing::Span,
+    }
+
+    impl Context {
+        /// Runs `f` in this context, its spans being children of the current one
+        pub(crate) fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+            dispatcher::with_default(&self.dispatch, || self.span.in_scope(f))
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use crate::ExecutionContext;
+
+    /// Does nothing: fake-ci was built without the `tracing` feature
+    pub(crate) struct Span;
+
+    /// Does nothing either
+    pub(crate) struct Entered;
+
+    impl Span {
+        pub(crate) fn pipeline(_ctx: &ExecutionContext) -> Self {
+            Span
+        }
+
+        pub(crate) fn job(_name: &str) -> Self {
+            Span
+        }
+
+        pub(crate) fn step(_name: &str) -> Self {
+            Span
+        }
+
+        pub(crate) fn enter(&self) -> Entered {
+            Entered
+        }
+
+        pub(crate) fn end(&self, _status: &str) {}
+
+        pub(crate) fn context(&self) -> Context {
+            Context
+        }
+    }
+
+    /// Does nothing as well
+    pub(crate) struct Context;
+
+    impl Context {
+        pub(crate) fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+            f()
+        }
+    }
+}
+
+pub(crate) use imp::Span;
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::git::Commit;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::utils::vcs::{RefDiff, RefUpdate};
+
+    fn refs(r: &[(&str, &str)]) -> HashMap<String, String> {
+        r.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_created() {
+        let d = RefDiff::between(&refs(&[]), &refs(&[("main", "aaa")]));
+        assert_eq!(d.created, refs(&[("main", "aaa")]));
+        assert!(d.updated.is_empty());
+        assert!(d.deleted.is_empty());
+    }
+
+    #[test]
+    fn diff_updated() {
+        let d = RefDiff::between(
+            &refs(&[("main", "aaa"), ("dev", "bbb")]),
+            &refs(&[("main", "aaa"), ("dev", "ccc")]),
+        );
+        assert!(d.created.is_empty());
+        assert_eq!(d.updated.len(), 1);
+        assert_eq!(
+            d.updated.get("dev"),
+            Some(&RefUpdate {
+                old: "bbb".to_string(),
+                new: "ccc".to_string()
+            })
+        );
+        assert!(d.deleted.is_empty());
+    }
+
+    #[test]
+    fn diff_deleted() {
+        let d = RefDiff::between(
+            &refs(&[("main", "aaa"), ("dev", "bbb")]),
+            &refs(&[("main", "aaa")]),
+        );
+        assert!(d.created.is_empty());
+        assert!(d.updated.is_empty());
+        assert_eq!(d.deleted, refs(&[("dev", "bbb")]));
+    }
+
+    #[test]
+    fn diff_unchanged() {
+        let r = refs(&[("main", "aaa")]);
+        let d = RefDiff::between(&r, &r);
+        assert!(d.is_empty());
+        assert_eq!(d.changed().count(), 0);
+        assert_eq!(d.to_string(), "no branch changed");
+    }
+
+    #[test]
+    fn diff_display() {
+        let d = RefDiff::between(
+            &refs(&[
+                ("main", "abc1234567890"),
+                ("old", "0000000"),
+                ("dev", "bbb"),
+            ]),
+            &refs(&[
+                ("main", "def5678901234"),
+                ("release/2", "1111111"),
+                ("dev", "bbb"),
+            ]),
+        );
+        assert_eq!(
+            d.to_string(),
+            "3 branches changed (main abc1234→def5678, old deleted, release/2 created)"
+        );
+        let d = RefDiff::between(&refs(&[]), &refs(&[("main", "aaa")]));
+        assert_eq!(d.to_string(), "1 branch changed (main created)");
+    }
+}
+
+/// Defines a version control backend, used to list, clone & inspect repositories.
+///
+/// [GitVcs](crate::utils::git::GitVcs) is the default implementation, shelling out to `git`.
+pub trait Vcs {
+    /// Lists the refs (branches) available on the remote at `uri`, as a map of
+    /// `branch name => commit hash`
+    fn list_refs(&self, uri: &str) -> Result<HashMap<String, String>>;
+    /// Returns the name of the default branch of the remote at `uri`
+    fn default_branch(&self, uri: &str) -> Result<String>;
+    /// Clones the repository at `uri` into `to`, then checkouts `reference`
+    fn clone_ref(&self, uri: &str, reference: &str, to: &Path) -> Result<()>;
+    /// Clones only the tip of `reference`, a branch or tag, of the repository at `uri` into `to`:
+    /// enough to read its files. By default, a full [clone](Vcs::clone_ref)
+    fn shallow_clone_ref(&self, uri: &str, reference: &str, to: &Path) -> Result<()> {
+        self.clone_ref(uri, reference, to)
+    }
+    /// Returns the commit currently checked out in the repository at `repo`
+    fn head_commit(&self, repo: &Path) -> Result<Commit>;
+    /// Returns the commit `reference` designates in the repository at `repo`. By default, the
+    /// [head commit](Vcs::head_commit): clones are checked out at the reference they're asked for
+    fn commit(&self, repo: &Path, _reference: &str) -> Result<Commit> {
+        self.head_commit(repo)
+    }
+    /// Tells what `reference` designates in the repository at `repo`, once cloned.
+    /// By default, a branch: that's all [list_refs](Vcs::list_refs) lists
+    fn ref_type(&self, _repo: &Path, _reference: &str) -> Result<RefType> {
+        Ok(RefType::Branch)
+    }
+    /// Does the repository at `repo` have uncommitted changes? By default, no: clones are clean
+    fn is_dirty(&self, _repo: &Path) -> Result<bool> {
+        Ok(false)
+    }
+    /// Lists the files changed between the commits `old` & `new` of the remote at `uri`.
+    /// Not supported by default
+    fn changed_files(&self, _uri: &str, _old: &str, _new: &str) -> Result<Vec<String>> {
+        Err(anyhow!("this backend can't list changed files"))
+    }
+    /// Lists the commits after `old`, up to `new`, of the remote at `uri`, oldest first.
+    /// Not supported by default
+    fn commits_between(&self, _uri: &str, _old: &str, _new: &str) -> Result<Vec<String>> {
+        Err(anyhow!("this backend can't list commits"))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// What a ref designates
+pub enum RefType {
+    /// A branch, which moves with new commits
+    Branch,
+    /// A tag, checked out as a detached HEAD
+    Tag,
+    /// A raw commit hash, checked out as a detached HEAD
+    Commit,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+/// A ref that moved from a commit to another
+pub struct RefUpdate {
+    /// The commit the ref used to point to
+    pub old: String,
+    /// The commit the ref now points to
+    pub new: String,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+/// The differences between two listings of refs, as returned by [Vcs::list_refs]
+pub struct RefDiff {
+    /// Refs that appeared, with the commit they point to
+    pub created: HashMap<String, String>,
+    /// Refs that now point to another commit
+    pub updated: HashMap<String, RefUpdate>,
+    /// Refs that disappeared, with the last commit they pointed to
+    pub deleted: HashMap<String, String>,
+}
+
+impl RefDiff {
+    /// Computes what changed to go from the `old` refs to the `new` ones
+    pub fn between(old: &HashMap<String, String>, new: &HashMap<String, String>) -> Self {
+        let mut diff = RefDiff::default();
+        for (k, v) in new {
+            match old.get(k) {
+                None => {
+                    diff.created.insert(k.to_string(), v.to_string());
+                }
+                Some(o) if o != v => {
+                    diff.updated.insert(
+                        k.to_string(),
+                        RefUpdate {
+                            old: o.to_string(),
+                            new: v.to_string(),
+                        },
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        diff.deleted.extend(
+            old.iter()
+                .filter(|(k, _)| !new.contains_key(*k))
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        diff
+    }
+
+    /// Returns true if nothing changed at all
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.updated.is_empty() && self.deleted.is_empty()
+    }
+
+    /// How many refs changed, whichever way
+    fn len(&self) -> usize {
+        self.created.len() + self.updated.len() + self.deleted.len()
+    }
+
+    /// Iterates over the refs pointing to a new commit (created or updated), with said commit
+    pub fn changed(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.created
+            .iter()
+            .chain(self.updated.iter().map(|(k, u)| (k, &u.new)))
+    }
+}
+
+/// Summarizes the diff in one line, sorted by branch, with the commits shortened. Ex:
+/// `2 branches changed (main abc1234→def5678, release/2 created)`
+impl fmt::Display for RefDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no branch changed");
+        }
+        let short = |hash: &str| hash.chars().take(7).collect::<String>();
+        let mut changes: Vec<(&String, String)> = self
+            .created
+            .keys()
+            .map(|k| (k, format!("{} created", k)))
+            .chain(
+                self.updated
+                    .iter()
+                    .map(|(k, u)| (k, format!("{} {}→{}", k, short(&u.old), short(&u.new)))),
+            )
+            .chain(self.deleted.keys().map(|k| (k, format!("{} deleted", k))))
+            .collect();
+        changes.sort();
+        write!(
+            f,
+            "{} {} changed ({})",
+            self.len(),
+            match self.len() {
+                1 => "branch",
+                _ => "branches",
+            },
+            changes
+                .into_iter()
+                .map(|(_, c)| c)
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}